@@ -0,0 +1,33 @@
+/*Generate a path on the 256 pixel sample image with each color metric and with the residual scorer, and print how long
+    it took and the mean ΔE of the result to the input under every metric. The ΔE are not scaled, as CIE94 and CIEDE2000
+    have no common maximum to scale them by. Run with `cargo bench --bench compare_metrics`.
+ */
+use stringwind::image_module::lab::ColorMetric;
+use stringwind::string_path::{string_path::StringPath, sample::sample_settings};
+
+use std::time::Instant;
+
+fn main()
+{
+    let header: Vec<String> = ColorMetric::ALL.iter().map(|m| format!("{m:?}")).collect();
+    println!("{:<20}{:>8}{:>10}  mean ΔE to the input by {}", "scoring", "steps", "ms", header.join(", "));
+    let runs = ColorMetric::ALL.iter()
+        .map(|metric| (format!("{metric:?}"), format!("color_metric = {}", serde_json::to_string(metric).unwrap())))
+        .chain([("Residual".to_string(), "scorer = \"residual\"".to_string())]);
+    for (label, extra) in runs
+    {
+        let extra = format!("pin_count = 150\nline_count = 1500\nseed = 2\n{extra}");
+        let mut sp = StringPath::new(sample_settings(&format!("compare_{label}"), 256, &extra).unwrap()).unwrap();
+        let start = Instant::now();
+        while sp.step().is_ok() {}
+        let elapsed = start.elapsed().as_millis();
+        let (input, drawn) = (sp.input_image(), &sp.strings_drawn);
+        let pixel_count = input.width() * input.height();
+        let means: Vec<String> = ColorMetric::ALL.iter().map(|m|
+        {
+            let sum: f32 = (0..pixel_count).map(|i| m.delta_e(&input.pixel_at(i), &drawn.pixel_at(i))).sum();
+            format!("{:.2}", sum / pixel_count as f32)
+        }).collect();
+        println!("{label:<20}{:>8}{elapsed:>10}  {}", sp.path.len(), means.join(", "));
+    }
+}
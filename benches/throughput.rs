@@ -0,0 +1,39 @@
+/*Steps per second on the 512 pixel sample image with 250 pins, for each way of invalidating cached scores.
+    Every timed step places a string, least_bad keeps the path going once no string improves the image. Each mode is
+    run several times from a new path and the median is reported. Run with `cargo bench --bench throughput`.
+ */
+use stringwind::string_path::{string_path::StringPath, sample::sample_settings};
+
+use std::time::Instant;
+
+const STEPS : usize = 300;
+const RUNS : usize = 5;
+
+fn main()
+{
+    println!("{:<16}{:>14}{:>14}{:>14}", "invalidation", "first step ms", "steps/sec", "slowest run");
+    for invalidation in ["cells", "intersection"]
+    {
+        let extra = format!("pin_count = 250\nline_count = 1000000\nseed = 1\nno_move_policy = \"least_bad\"\nscore_invalidation = \"{invalidation}\"");
+        let settings = sample_settings(&format!("throughput_{invalidation}"), 512, &extra).unwrap();
+        let mut first_steps = Vec::new();
+        let mut rates = Vec::new();
+        for _ in 0..RUNS
+        {
+            let mut sp = StringPath::new(settings.clone()).unwrap();
+            //The first step scores every line, later steps only rescore the lines the previous string affected
+            let start = Instant::now();
+            sp.step().unwrap();
+            first_steps.push(start.elapsed().as_secs_f64() * 1000.);
+            let start = Instant::now();
+            for _ in 0..STEPS
+            {
+                sp.step().unwrap();
+            }
+            rates.push(STEPS as f64 / start.elapsed().as_secs_f64());
+        }
+        first_steps.sort_by(f64::total_cmp);
+        rates.sort_by(f64::total_cmp);
+        println!("{invalidation:<16}{:>14.1}{:>14.1}{:>14.1}", first_steps[RUNS / 2], rates[RUNS / 2], rates[0]);
+    }
+}
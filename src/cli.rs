@@ -0,0 +1,311 @@
+use crate::string_path::{
+    string_path::StringPath,
+    string_setting::{StringSettings, read_string_settings, read_string_settings_with_overrides},
+    path_generation::{generate_path, resume_path},
+    export::WindingInstructions,
+};
+use crate::image_module::lab::LabBuf;
+use crate::image_module::color_names::ColorNames;
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use clap::{Parser, Subcommand, Args};
+
+#[derive(Parser)]
+#[command(name = "stringwind", about = "Generate string art winding paths from images.")]
+#[command(after_help = "Exit codes: 0 on success, 2 on invalid arguments, 3 on settings errors, 4 on path errors, 5 on output errors.")]
+pub struct Cli
+{
+    #[command(subcommand)]
+    pub command : Command
+}
+
+#[derive(Subcommand)]
+pub enum Command
+{
+    /// Generate a path from a settings file, saving the image and winding instructions
+    Generate(GenerateArgs),
+    /// Continue generating a path from its latest checkpoint. The settings must match the checkpointed run, a run without a seed continues with the one it drew
+    Resume(GenerateArgs),
+    /// Re-render the image of previously exported winding instructions
+    Render
+    {
+        instructions : PathBuf,
+        /// Output image, defaults to the instructions path with a .png extension
+        #[arg(short, long)]
+        output : Option<PathBuf>
+    },
+    /// Convert previously exported winding instructions to JSON and/or CSV
+    Export
+    {
+        instructions : PathBuf,
+        #[arg(long)]
+        json : Option<PathBuf>,
+        #[arg(long)]
+        csv : Option<PathBuf>
+    },
+    /// Compare the images of previously exported winding instructions to their input image, to compare runs
+    Evaluate
+    {
+        instructions : Vec<PathBuf>
+    },
+    /// Print a summary of a settings file or of exported winding instructions
+    Info
+    {
+        file : PathBuf
+    }
+}
+
+#[derive(Args)]
+pub struct GenerateArgs
+{
+    #[command(flatten)]
+    pub overrides : SettingOverrides,
+    /// Show the path in a window while it is generated
+    #[arg(long)]
+    pub preview : bool
+}
+
+#[derive(Args)]
+pub struct SettingOverrides
+{
+    pub settings : PathBuf,
+    /// Input image, overrides in_image_path
+    #[arg(short, long)]
+    pub input : Option<String>,
+    /// Output directory, overrides out_image_path
+    #[arg(short, long)]
+    pub output : Option<String>,
+    #[arg(long)]
+    pub pin_count : Option<usize>,
+    #[arg(long)]
+    pub line_count : Option<usize>,
+    #[arg(long)]
+    pub edge_weight : Option<f32>,
+    /// Seed for the random choice between colors, drawn at random if not set
+    #[arg(long)]
+    pub seed : Option<u64>,
+    /// Override any scalar setting, as key=value
+    #[arg(long = "set", value_name = "KEY=VALUE", value_parser = parse_key_value)]
+    pub set : Vec<(String, String)>
+}
+
+pub enum CliError
+{
+    Settings(String),
+    Path(String),
+    Output(String)
+}
+
+impl CliError
+{
+    pub fn exit_code(&self) -> i32
+    {
+        match self
+        {
+            CliError::Settings(_) => 3,
+            CliError::Path(_) => 4,
+            CliError::Output(_) => 5
+        }
+    }
+
+    pub fn message(&self) -> &str
+    {
+        match self
+        {
+            CliError::Settings(msg) | CliError::Path(msg) | CliError::Output(msg) => msg
+        }
+    }
+}
+
+impl Command
+{
+    //Whether the command needs a window, and therefore has to run inside the show_image context
+    pub fn needs_window(&self) -> bool
+    {
+        matches!(self, Command::Generate(GenerateArgs {preview: true, ..}) | Command::Resume(GenerateArgs {preview: true, ..}))
+    }
+}
+
+impl SettingOverrides
+{
+    fn read(&self) -> Result<StringSettings, CliError>
+    {
+        let mut overrides = self.set.clone();
+        let named = [
+            ("in_image_path", self.input.clone()),
+            ("out_image_path", self.output.clone()),
+            ("pin_count", self.pin_count.map(|v| v.to_string())),
+            ("line_count", self.line_count.map(|v| v.to_string())),
+            ("edge_weight", self.edge_weight.map(|v| v.to_string())),
+            ("seed", self.seed.map(|v| v.to_string()))
+        ];
+        for (key, value) in named
+        {
+            if let Some(value) = value
+            {
+                overrides.push((key.to_string(), value));
+            }
+        }
+        read_string_settings_with_overrides(&self.settings, &overrides)
+            .map_err(|e| CliError::Settings(e.to_string()))
+    }
+}
+
+fn parse_key_value(arg: &str) -> Result<(String, String), String>
+{
+    match arg.split_once('=')
+    {
+        Some((key, value)) if !key.is_empty() => Ok((key.trim().to_string(), value.trim().to_string())),
+        _ => Err(format!("expected KEY=VALUE, got \"{arg}\""))
+    }
+}
+
+fn load_instructions(path: &Path) -> Result<WindingInstructions, CliError>
+{
+    WindingInstructions::load_json(&path.to_string_lossy()).map_err(CliError::Path)
+}
+
+pub fn run(command: Command) -> Result<(), CliError>
+{
+    match command
+    {
+        Command::Generate(args) =>
+        {
+            let settings = args.overrides.read()?;
+            let path = generate_path(settings, args.preview).map_err(CliError::Path)?;
+            path.save_visual().map_err(CliError::Output)?;
+            path.save_instructions().map_err(CliError::Output)
+        },
+        Command::Resume(args) =>
+        {
+            let settings = args.overrides.read()?;
+            let path = resume_path(settings, args.preview).map_err(CliError::Path)?;
+            path.save_visual().map_err(CliError::Output)?;
+            path.save_instructions().map_err(CliError::Output)
+        },
+        Command::Render {instructions, output} =>
+        {
+            let sp = StringPath::render_instructions(&load_instructions(&instructions)?).map_err(CliError::Path)?;
+            let output = output.unwrap_or_else(|| instructions.with_extension("png"));
+            sp.render().save(&output.to_string_lossy()).map_err(|e| CliError::Output(e.to_string()))
+        },
+        Command::Export {instructions, json, csv} =>
+        {
+            if json.is_none() && csv.is_none()
+            {
+                return Err(CliError::Output("Nothing to export, pass --json and/or --csv.".to_string()));
+            }
+            let loaded = load_instructions(&instructions)?;
+            if let Some(json) = json
+            {
+                loaded.save_json(&json.to_string_lossy()).map_err(CliError::Output)?;
+            }
+            if let Some(csv) = csv
+            {
+                loaded.save_csv(&csv.to_string_lossy()).map_err(CliError::Output)?;
+            }
+            Ok(())
+        },
+        Command::Evaluate {instructions} =>
+        {
+            println!("{:>10}{:>14}{:>10}{:>12}  instructions", "steps", "mean ΔE", "SSIM", "PSNR (dB)");
+            for file in instructions
+            {
+                let loaded = load_instructions(&file)?;
+                if !Path::new(&loaded.settings.in_image_path).exists()
+                {
+                    return Err(CliError::Path(format!("{}: input image {} is unavailable", file.display(), loaded.settings.in_image_path)));
+                }
+                let quality = StringPath::render_instructions(&loaded).map_err(CliError::Path)?.evaluate();
+                println!("{:>10}{:>14.2}{:>10.4}{:>12.2}  {}", quality.step, quality.mean_delta_e, quality.ssim, quality.psnr, file.display());
+            }
+            Ok(())
+        },
+        Command::Info {file} =>
+        {
+            if file.extension().is_some_and(|e| e == "json")
+            {
+                print_instructions_info(&load_instructions(&file)?);
+                return Ok(());
+            }
+            let settings = read_string_settings(&file).map_err(|e| CliError::Settings(e.to_string()))?;
+            print_settings_info(&settings)
+        }
+    }
+}
+
+fn print_settings_info(settings: &StringSettings) -> Result<(), CliError>
+{
+    println!("{}", serde_json::to_string_pretty(settings).map_err(|e| CliError::Output(e.to_string()))?);
+    let color_names = match &settings.color_names
+    {
+        Some(path) => Arc::new(ColorNames::load(path).map_err(CliError::Settings)?),
+        None => ColorNames::builtin()
+    };
+    for (idx, color) in settings.colors().iter().enumerate()
+    {
+        println!("Color {idx}: {}", color_names.nearest(color));
+    }
+    Ok(())
+}
+
+fn print_instructions_info(instructions: &WindingInstructions)
+{
+    println!("Image dimensions: {}x{}", instructions.image_dimensions.0, instructions.image_dimensions.1);
+    println!("Pins: {}", instructions.pins.len());
+    println!("Steps: {}", instructions.step_count);
+    println!("Seed: {}", instructions.seed);
+    for color in instructions.colors.iter()
+    {
+        println!("Color {}: {} ({} steps)", color.index, color.name, color.steps.len());
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, clap::Error>
+    {
+        Cli::try_parse_from(std::iter::once("stringwind").chain(args.iter().copied())).map(|cli| cli.command)
+    }
+
+    #[test]
+    fn commands_are_parsed()
+    {
+        let Ok(Command::Generate(args)) = parse(&["generate", "settings.toml", "--seed", "3", "-i", "in.png", "--set", "min_score = 0.1", "--preview"])
+            else {panic!("generate was not parsed")};
+        assert_eq!((args.overrides.settings, args.overrides.seed, args.overrides.input), (PathBuf::from("settings.toml"), Some(3), Some("in.png".to_string())));
+        assert_eq!(args.overrides.set, vec![("min_score".to_string(), "0.1".to_string())]);
+        assert!(args.preview);
+
+        let Ok(Command::Render {instructions, output}) = parse(&["render", "path.json", "-o", "path.png"]) else {panic!("render was not parsed")};
+        assert_eq!((instructions, output), (PathBuf::from("path.json"), Some(PathBuf::from("path.png"))));
+        let Ok(Command::Export {instructions, json, csv}) = parse(&["export", "path.json", "--csv", "path.csv"]) else {panic!("export was not parsed")};
+        assert_eq!((instructions, json, csv), (PathBuf::from("path.json"), None, Some(PathBuf::from("path.csv"))));
+        let Ok(Command::Info {file}) = parse(&["info", "settings.toml"]) else {panic!("info was not parsed")};
+        assert_eq!(file, PathBuf::from("settings.toml"));
+
+        //Usage errors are reported by clap, which exits with 2
+        use clap::error::ErrorKind;
+        assert_eq!(parse(&["generate"]).err().unwrap().kind(), ErrorKind::MissingRequiredArgument);
+        assert_eq!(parse(&["generate", "settings.toml", "--set", "min_score"]).err().unwrap().kind(), ErrorKind::ValueValidation);
+        assert_eq!(parse(&["render", "path.json", "--json", "x.json"]).err().unwrap().kind(), ErrorKind::UnknownArgument);
+    }
+
+    #[test]
+    fn errors_map_to_exit_codes()
+    {
+        let missing = std::env::temp_dir().join("stringwind_cli").join("missing");
+        let exit_code = |args: &[&str]| run(parse(args).unwrap()).unwrap_err().exit_code();
+        let settings = missing.with_extension("toml");
+        let instructions = missing.with_extension("json");
+        assert_eq!(exit_code(&["generate", settings.to_str().unwrap()]), 3);
+        assert_eq!(exit_code(&["info", settings.to_str().unwrap()]), 3);
+        assert_eq!(exit_code(&["render", instructions.to_str().unwrap()]), 4);
+        assert_eq!(exit_code(&["info", instructions.to_str().unwrap()]), 4);
+        assert_eq!(exit_code(&["export", instructions.to_str().unwrap()]), 5);
+    }
+}
@@ -0,0 +1,95 @@
+use palette::Lab;
+use rstar::{RTree, PointDistance, primitives::GeomWithData};
+use std::sync::{Arc, OnceLock};
+
+//Table of named colors, indexed by their Lab values
+pub struct ColorNames
+{
+    tree : RTree<GeomWithData<[f32; 3], usize>>,
+    names : Vec<String>
+}
+
+static BUILTIN : OnceLock<Arc<ColorNames>> = OnceLock::new();
+
+impl ColorNames
+{
+    //The table in src/data/color_names.csv, embedded in the binary so that it does not depend on the working directory
+    pub fn builtin() -> Arc<ColorNames>
+    {
+        BUILTIN.get_or_init(||
+        {
+            let table = ColorNames::parse(include_str!("../data/color_names.csv")).expect("src/data/color_names.csv is valid");
+            Arc::new(table)
+        }).clone()
+    }
+
+    //Read a table with a name column followed by L, a and b columns, like src/data/color_names.csv
+    pub fn load(path: &str) -> Result<ColorNames, String>
+    {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        ColorNames::parse(&text).map_err(|e| format!("{path}: {e}"))
+    }
+
+    pub fn parse(text: &str) -> Result<ColorNames, String>
+    {
+        let (names, points) = parse_named_colors(text)?.into_iter().enumerate()
+            .map(|(idx, (name, color))| (name, GeomWithData::new([color.l, color.a, color.b], idx)))
+            .unzip();
+        Ok(ColorNames {tree: RTree::bulk_load(points), names})
+    }
+
+    //Name of the closest color in Lab space
+    pub fn nearest(&self, color: &Lab) -> &str
+    {
+        let entry = self.tree.nearest_neighbor(&[color.l, color.a, color.b]).unwrap();
+        &self.names[entry.data]
+    }
+
+    //The k closest names, closest first, with their Euclidean Lab distance to the color
+    #[allow(dead_code)]
+    pub fn top_k(&self, color: &Lab, k: usize) -> Vec<(&str, f32)>
+    {
+        let point = [color.l, color.a, color.b];
+        self.tree.nearest_neighbor_iter(&point)
+            .take(k)
+            .map(|entry| (self.names[entry.data].as_str(), entry.distance_2(&point).sqrt()))
+            .collect()
+    }
+}
+
+//Rows of a CSV table with a name column followed by L, a and b columns, like src/data/color_names.csv
+pub fn parse_named_colors(text: &str) -> Result<Vec<(String, Lab)>, String>
+{
+    let mut reader = csv::Reader::from_reader(text.as_bytes());
+    let mut colors = Vec::new();
+    for (row, record) in reader.records().enumerate()
+    {
+        let record = record.map_err(|e| e.to_string())?;
+        let values: Option<Vec<f32>> = (1..4).map(|col| record.get(col).and_then(|v| v.trim().parse().ok())).collect();
+        let values = values.ok_or(format!("row {} does not have a name followed by L, a and b values", row + 1))?;
+        colors.push((record[0].trim().to_string(), Lab::new(values[0], values[1], values[2])));
+    }
+    if colors.is_empty()
+    {
+        return Err("the color table is empty".to_string());
+    }
+    Ok(colors)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn nearest_names()
+    {
+        let names = ColorNames::builtin();
+        assert_eq!(names.nearest(&Lab::new(0., 0., 0.)), "Black");
+        let table = ColorNames::parse("name,L,a,b\nDark,10,0,0\nMid,50,0,0\nLight,90,0,0\n").unwrap();
+        let top = table.top_k(&Lab::new(40., 0., 0.), 2);
+        assert_eq!(top, vec![("Mid", 10.), ("Dark", 30.)]);
+        assert!(ColorNames::parse("name,L,a,b\nBroken,1,2\n").is_err());
+        assert!(parse_named_colors("name,L,a,b\n").is_err());
+    }
+}
@@ -0,0 +1,188 @@
+use super::lab::{LabImageBuffer, LabBuf};
+
+use image::{RgbImage, Rgb};
+use palette::{Lab, ColorDifference};
+use rayon::prelude::*;
+use serde::{Serialize, Deserialize};
+
+//Smallest detail the eye resolves, one arcminute
+const EYE_RESOLUTION_RAD : f32 = std::f32::consts::PI / (180. * 60.);
+//Window of the SSIM means and variances, as in Wang et al. 2004
+const SSIM_SIGMA : f32 = 1.5;
+//Regions of the error map with this mean ΔE or more are white
+const ERROR_MAP_MAX_DELTA_E : f32 = 30.;
+//Number of regions along the longer side of the error map
+const ERROR_MAP_REGIONS : u32 = 32;
+
+//How close the drawn strings are to the input image, after both are blurred as seen from the viewing distance
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Evaluation
+{
+    pub step : usize,
+    pub mean_delta_e : f32, //CIEDE2000, in ΔE units
+    pub ssim : f32, //Structural similarity of the lightness, 1 is identical
+    pub psnr : f32 //Of the sRGB values, in dB
+}
+
+//Standard deviation in pixels of the blur which hides details the eye cannot resolve from the viewing distance
+pub fn viewing_blur_sigma(viewing_distance_mm: f32, mm_per_pixel: f32) -> f32
+{
+    viewing_distance_mm * EYE_RESOLUTION_RAD.tan() / mm_per_pixel / 2.
+}
+
+pub fn evaluate(step: usize, drawn: &LabImageBuffer, target: &LabImageBuffer, blur_sigma: f32) -> Evaluation
+{
+    let drawn = blur(drawn, blur_sigma);
+    let target = blur(target, blur_sigma);
+    let pixel_count = drawn.as_raw().len() / 3;
+    let delta_e = delta_e_map(&drawn, &target);
+
+    let drawn_rgb = drawn.as_rgb_image_buffer();
+    let target_rgb = target.as_rgb_image_buffer();
+    let squared_error: f32 = drawn_rgb.as_raw().par_iter().zip(target_rgb.as_raw().par_iter())
+        .map(|(a, b)| (a.clamp(0., 1.) - b.clamp(0., 1.)).powi(2))
+        .sum();
+    let mse = squared_error / (pixel_count * 3) as f32;
+
+    Evaluation
+    {
+        step,
+        mean_delta_e: delta_e.iter().sum::<f32>() / pixel_count as f32,
+        ssim: ssim(&drawn, &target),
+        psnr: 10. * (1. / mse).log10()
+    }
+}
+
+//One row per logged evaluation
+pub fn save_quality_log(log: &[Evaluation], path: &str) -> Result<(), String>
+{
+    let mut writer = csv::Writer::from_path(path).map_err(|e| format!("{path}: {e}"))?;
+    for evaluation in log
+    {
+        writer.serialize(evaluation).map_err(|e| format!("{path}: {e}"))?;
+    }
+    writer.flush().map_err(|e| format!("{path}: {e}"))
+}
+
+//Mean ΔE of square regions of the image, from black for no error to white for ERROR_MAP_MAX_DELTA_E or more
+pub fn error_map(drawn: &LabImageBuffer, target: &LabImageBuffer, blur_sigma: f32) -> RgbImage
+{
+    let (width, height) = drawn.dimensions();
+    let delta_e = delta_e_map(&blur(drawn, blur_sigma), &blur(target, blur_sigma));
+    let region_size = width.max(height).div_ceil(ERROR_MAP_REGIONS).max(1);
+    let (columns, rows) = (width.div_ceil(region_size), height.div_ceil(region_size));
+    let mut sums = vec![(0_f32, 0_u32); (columns * rows) as usize];
+    for (idx, error) in delta_e.iter().enumerate()
+    {
+        let (x, y) = (idx as u32 % width, idx as u32 / width);
+        let region = &mut sums[((y / region_size) * columns + x / region_size) as usize];
+        region.0 += error;
+        region.1 += 1;
+    }
+    RgbImage::from_fn(width, height, |x, y|
+    {
+        let (sum, count) = sums[((y / region_size) * columns + x / region_size) as usize];
+        let value = (sum / count as f32 / ERROR_MAP_MAX_DELTA_E).clamp(0., 1.);
+        Rgb([(value * 255.).round() as u8; 3])
+    })
+}
+
+fn delta_e_map(drawn: &LabImageBuffer, target: &LabImageBuffer) -> Vec<f32>
+{
+    drawn.as_raw().par_chunks(3).zip(target.as_raw().par_chunks(3))
+        .map(|(a, b)| Lab::new(a[0], a[1], a[2]).get_color_difference(&Lab::new(b[0], b[1], b[2])))
+        .collect()
+}
+
+//Gaussian blur of every channel, nothing is blurred for a sigma of 0
+pub fn blur(image: &LabImageBuffer, sigma: f32) -> LabImageBuffer
+{
+    let (width, height) = image.dimensions();
+    let raw = image.as_raw();
+    let channels: Vec<Vec<f32>> = (0..3)
+        .map(|c| blur_plane(&raw.iter().skip(c).step_by(3).copied().collect::<Vec<f32>>(), width as usize, sigma))
+        .collect();
+    let blurred = (0..raw.len()).map(|i| channels[i % 3][i / 3]).collect();
+    LabImageBuffer::from_raw(width, height, blurred).unwrap()
+}
+
+//Separable Gaussian blur of a single channel, clamping at the edges
+fn blur_plane(plane: &[f32], width: usize, sigma: f32) -> Vec<f32>
+{
+    if sigma <= 0. || plane.is_empty() {return plane.to_vec()};
+    let height = plane.len() / width;
+    let radius = (sigma * 3.).ceil() as isize;
+    let kernel: Vec<f32> = (-radius..=radius).map(|i| (-(i * i) as f32 / (2. * sigma * sigma)).exp()).collect();
+    let kernel_sum: f32 = kernel.iter().sum();
+    let convolve = |get: &dyn Fn(isize) -> f32| -> f32
+    {
+        kernel.iter().enumerate().map(|(k, weight)| weight * get(k as isize - radius)).sum::<f32>() / kernel_sum
+    };
+    let mut horizontal = vec![0.; plane.len()];
+    horizontal.par_chunks_mut(width).enumerate().for_each(|(y, row)|
+    {
+        for (x, value) in row.iter_mut().enumerate()
+        {
+            *value = convolve(&|d| plane[y * width + (x as isize + d).clamp(0, width as isize - 1) as usize]);
+        }
+    });
+    let mut vertical = vec![0.; plane.len()];
+    vertical.par_chunks_mut(width).enumerate().for_each(|(y, row)|
+    {
+        for (x, value) in row.iter_mut().enumerate()
+        {
+            *value = convolve(&|d| horizontal[(y as isize + d).clamp(0, height as isize - 1) as usize * width + x]);
+        }
+    });
+    vertical
+}
+
+//Mean structural similarity of the lightness channel
+fn ssim(a: &LabImageBuffer, b: &LabImageBuffer) -> f32
+{
+    const C1 : f32 = (0.01 * 100.) * (0.01 * 100.);
+    const C2 : f32 = (0.03 * 100.) * (0.03 * 100.);
+    let width = a.width() as usize;
+    let lightness = |image: &LabImageBuffer| -> Vec<f32> {image.as_raw().iter().step_by(3).copied().collect()};
+    let (x, y) = (lightness(a), lightness(b));
+    let local_mean = |values: Vec<f32>| blur_plane(&values, width, SSIM_SIGMA);
+    let mean_x = local_mean(x.clone());
+    let mean_y = local_mean(y.clone());
+    let mean_xx = local_mean(x.iter().map(|v| v * v).collect());
+    let mean_yy = local_mean(y.iter().map(|v| v * v).collect());
+    let mean_xy = local_mean(x.iter().zip(&y).map(|(a, b)| a * b).collect());
+    let sum: f32 = (0..x.len()).into_par_iter().map(|i|
+    {
+        let (mx, my) = (mean_x[i], mean_y[i]);
+        let var_x = mean_xx[i] - mx * mx;
+        let var_y = mean_yy[i] - my * my;
+        let covariance = mean_xy[i] - mx * my;
+        ((2. * mx * my + C1) * (2. * covariance + C2)) / ((mx * mx + my * my + C1) * (var_x + var_y + C2))
+    }).sum();
+    sum / x.len() as f32
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn identical_images_score_perfectly()
+    {
+        let mut target = LabImageBuffer::from_lab(32, 32, &Lab::new(100., 0., 0.));
+        for x in 0..32 {target.put_pixel(x, 10, &Lab::new(0., 0., 0.))};
+        let same = evaluate(0, &target, &target, 1.);
+        assert!(same.mean_delta_e < 1e-3 && (same.ssim - 1.).abs() < 1e-3 && same.psnr.is_infinite(), "{same:?}");
+
+        let blank = LabImageBuffer::from_lab(32, 32, &Lab::new(100., 0., 0.));
+        let missing_line = evaluate(0, &blank, &target, 1.);
+        assert!(missing_line.mean_delta_e > 0. && missing_line.ssim < 1. && missing_line.psnr.is_finite(), "{missing_line:?}");
+        //Blurring as seen from further away hides the difference
+        assert!(evaluate(0, &blank, &target, 4.).psnr > missing_line.psnr);
+
+        let map = error_map(&blank, &target, 0.);
+        assert_eq!(map.get_pixel(0, 0)[0], 0);
+        assert!(map.get_pixel(0, 10)[0] > 0);
+    }
+}
@@ -1,7 +1,7 @@
 use image::{ImageBuffer, Rgb, Rgba, ImageResult, DynamicImage};
-use palette::{Lab, Srgb, Laba, Srgba, IntoColor, Mix};
+use palette::{Lab, Srgb, Laba, Srgba, IntoColor, Mix, ColorDifference};
+use serde::{Serialize, Deserialize};
 use rayon::prelude::*;
-use csv::Reader;
 use line_drawing::XiaolinWu;
 
 #[derive(Default)]
@@ -15,7 +15,30 @@ impl LabImageBuffer
     pub fn width(&self) -> u32 {self.buffer.width()}
     pub fn height(&self) -> u32 {self.buffer.height()}
     pub fn dimensions(&self) -> (u32, u32) {self.buffer.dimensions()}
-    pub fn draw_line(&mut self, start: (f32, f32), end: (f32, f32), color: &Lab, alpha_weight: bool)
+    //Raw L, a, b values of every pixel, row by row
+    pub fn as_raw(&self) -> &[f32] {self.buffer.as_raw()}
+    //Pixel at the given index into the row by row pixels
+    pub fn pixel_at(&self, idx: u32) -> Lab
+    {
+        let raw = &self.buffer.as_raw()[idx as usize * 3..idx as usize * 3 + 3];
+        Lab::new(raw[0], raw[1], raw[2])
+    }
+    pub fn from_raw(width: u32, height: u32, raw: Vec<f32>) -> Option<Self>
+    {
+        ImageBuffer::from_raw(width, height, raw).map(|buffer| Self {buffer})
+    }
+    //Same as draw_line, with the line's color only partially covering the pixels
+    pub fn draw_translucent_line(&mut self, start: (f32, f32), end: (f32, f32), color: &Lab, opacity: f32)
+    {
+        let line = XiaolinWu::<f32, i32>::new(start, end);
+        line.for_each(|((x,y), weight)|
+        {
+            let background = self.get_pixel(x as u32,y as u32);
+            self.put_pixel(x as u32, y as u32, &background.mix(color, weight * opacity));
+        });
+    }
+    #[allow(dead_code)]
+    pub fn draw_line(&mut self, start: (f32, f32), end: (f32, f32), color: &Lab, _alpha_weight: bool)
     {
         let line = XiaolinWu::<f32, i32>::new(start, end);
         line.for_each(|((x,y), weight)| 
@@ -27,12 +50,14 @@ impl LabImageBuffer
     }
 }
 
+#[allow(dead_code)]
 #[derive(Default)]
 pub struct LabaImageBuffer
 {
     buffer: ImageBuffer<Rgba<f32>, Vec<f32>>
 }
 
+#[allow(dead_code)]
 impl LabaImageBuffer
 {
     pub fn width(&self) -> u32 {self.buffer.width()}
@@ -82,11 +107,11 @@ impl LabBuf for LabImageBuffer
     fn get_pixel(&self, x: u32, y: u32) -> Self::LabType
     {
         let pix_rgb = self.buffer.get_pixel(x,y);
-        return Lab::new(pix_rgb[0], pix_rgb[1], pix_rgb[2]);
+        Lab::new(pix_rgb[0], pix_rgb[1], pix_rgb[2])
     }
     fn put_pixel(&mut self, x: u32, y: u32, value: &Self::LabType)
     {
-        self.buffer.put_pixel(x, y, Rgb::<f32>{0: [value.l, value.a, value.b]});
+        self.buffer.put_pixel(x, y, Rgb::<f32>([value.l, value.a, value.b]));
     }
     fn new(width: u32, height: u32) -> Self
     {
@@ -98,14 +123,14 @@ impl LabBuf for LabImageBuffer
     fn as_rgb_image_buffer(&self) -> Self::BufferType 
     {
         let mut rgb_buffer = self.buffer.clone();
-        rgb_buffer.par_chunks_mut(4).for_each(|p|
+        rgb_buffer.par_chunks_mut(3).for_each(|p|
             {
                 let srgb: Srgb = Lab::new(p[0], p[1], p[2]).into_color();
                 p[0] = srgb.red;
                 p[1] = srgb.green;
                 p[2] = srgb.blue;
             });
-        return rgb_buffer;
+        rgb_buffer
     }
     fn from_rgb_image_buffer(buffer: &Self::BufferType) -> Self 
     {
@@ -125,7 +150,7 @@ impl LabBuf for LabImageBuffer
     fn from_file(path: &str) -> Result<Self, image::ImageError>
     {
         let binding = image::open(path)?;
-        let mut rgb_img = binding.into_rgb32f().clone();
+        let rgb_img = binding.into_rgb32f();
         Ok(Self::from_rgb_image_buffer(&rgb_img))
     }
     fn from_lab(width: u32, height: u32, color: &Lab) -> Self
@@ -159,11 +184,11 @@ impl LabBuf for LabaImageBuffer
     fn get_pixel(&self, x: u32, y: u32) -> Self::LabType
     {
         let pix_rgb = self.buffer.get_pixel(x,y);
-        return Laba::new(pix_rgb[0], pix_rgb[1], pix_rgb[2], pix_rgb[3]);
+        Laba::new(pix_rgb[0], pix_rgb[1], pix_rgb[2], pix_rgb[3])
     }
     fn put_pixel(&mut self, x: u32, y: u32, value: &Self::LabType)
     {
-        self.buffer.put_pixel(x, y, Rgba::<f32>{0: [value.l, value.a, value.b, value.alpha]});
+        self.buffer.put_pixel(x, y, Rgba::<f32>([value.l, value.a, value.b, value.alpha]));
     }
     fn new(width: u32, height: u32) -> Self
     {
@@ -183,7 +208,7 @@ impl LabBuf for LabaImageBuffer
                 p[2] = srgb.blue;
                 p[3] = srgb.alpha;
             });
-        return rgb_buffer;
+        rgb_buffer
     }
     fn from_rgb_image_buffer(buffer: &Self::BufferType) -> Self 
     {
@@ -204,7 +229,7 @@ impl LabBuf for LabaImageBuffer
     fn from_file(path: &str) -> Result<Self, image::ImageError>
     {
         let binding = image::open(path)?;
-        let mut rgba_img = binding.into_rgba32f().clone();
+        let rgba_img = binding.into_rgba32f();
         Ok(Self::from_rgb_image_buffer(&rgba_img))
     }
     fn from_lab(width: u32, height: u32, color: &Laba) -> Self
@@ -229,57 +254,171 @@ impl LabBuf for LabaImageBuffer
 }
 
 
-pub trait LabDifference
+/*How the difference between two colors is measured. Every metric is divided by its largest value between two colors
+    of the Lab range, so that differences lie in [0, 1] and similarities of different metrics can be compared.
+    CIE94 and CIEDE2000 only ever shrink chroma and hue differences, so they share the Euclidean maximum.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorMetric
 {
-    fn difference_from(&self, other: &Lab) -> f32;
-    fn similarity_to(&self, other: &Lab) -> f32;
+    //Straight distance between Lab colors (CIE76). Fast, and so far gives the most detail.
+    #[default]
+    #[serde(alias = "cie76")]
+    Euclidean,
+    //CIE94 with the graphic arts constants
+    Cie94,
+    //CIEDE2000, the most perceptually uniform, but a lot slower
+    Ciede2000,
+    //Euclidean, with lightness differences counting LUMINANCE_WEIGHT times as much as color differences
+    LuminanceWeighted
 }
 
-//Implements a color difference algorithm based on compile-time ettings.
-impl LabDifference for Lab
+//Largest Euclidean distance between two Lab colors, from black at a = b = -128 to white at a = b = 127
+const MAX_DIFF : f32 = 374.232_55;
+const LUMINANCE_WEIGHT : f32 = 2.;
+
+impl ColorMetric
 {
-    fn difference_from(&self, other: &Lab) -> f32 {
-        const MAX_DIFF : f32 = 374.232548023; //maximum difference between two Lab colors
-        //Use palette's built-in difference function. 
-        //  Preliminary tests show that this algorithm is a lot slower, and potentially results in less detail. More testing needed.
-        #[cfg(feature = "cielab_difference")] 
+    #[allow(dead_code)]
+    pub const ALL : [ColorMetric; 4] = [ColorMetric::Euclidean, ColorMetric::Cie94, ColorMetric::Ciede2000, ColorMetric::LuminanceWeighted];
+
+    //Difference scaled to 0 to 1, by the largest Euclidean distance for the metrics without a simple maximum
+    pub fn difference(&self, a: &Lab, b: &Lab) -> f32
+    {
+        match self
         {
-            return self.get_color_difference(other) / max_diff;
+            ColorMetric::LuminanceWeighted =>
+            {
+                let max_l = 100. * LUMINANCE_WEIGHT;
+                self.delta_e(a, b) / (max_l*max_l + MAX_DIFF*MAX_DIFF - 100.*100.).sqrt()
+            },
+            _ => self.delta_e(a, b) / MAX_DIFF
         }
-        //Use linear euclidean distance between Lab colors.
-        //  Currently seems like the best option.
-        #[cfg(not(feature = "cielab_difference"))]
+    }
+
+    //Unscaled difference, in the units of each metric, to compare results of different metrics by
+    pub fn delta_e(&self, a: &Lab, b: &Lab) -> f32
+    {
+        match self
         {
-            let diff = *self - *other;
-            return (diff.l*diff.l + diff.a*diff.a + diff.b*diff.b).sqrt() / MAX_DIFF;
+            ColorMetric::Euclidean =>
+            {
+                let diff = *a - *b;
+                (diff.l*diff.l + diff.a*diff.a + diff.b*diff.b).sqrt()
+            },
+            ColorMetric::Cie94 => cie94(a, b),
+            ColorMetric::Ciede2000 => a.get_color_difference(b),
+            ColorMetric::LuminanceWeighted =>
+            {
+                let diff = *a - *b;
+                ((LUMINANCE_WEIGHT*diff.l).powi(2) + diff.a*diff.a + diff.b*diff.b).sqrt()
+            }
         }
     }
-    fn similarity_to(&self, other: &Lab) -> f32 {
-        1.0 - self.difference_from(other)
+
+    pub fn similarity(&self, a: &Lab, b: &Lab) -> f32
+    {
+        1.0 - self.difference(a, b)
     }
 }
 
-pub fn get_color_name(color : &Lab) -> String
+fn cie94(a: &Lab, b: &Lab) -> f32
+{
+    const K1 : f32 = 0.045;
+    const K2 : f32 = 0.015;
+    let chroma_a = (a.a*a.a + a.b*a.b).sqrt();
+    let chroma_b = (b.a*b.a + b.b*b.b).sqrt();
+    let delta_l = a.l - b.l;
+    let delta_c = chroma_a - chroma_b;
+    //Whatever of the a and b difference is not explained by chroma is hue
+    let delta_h_sq = ((a.a - b.a).powi(2) + (a.b - b.b).powi(2) - delta_c*delta_c).max(0.);
+    let s_c = 1. + K1 * chroma_a;
+    let s_h = 1. + K2 * chroma_a;
+    (delta_l*delta_l + (delta_c/s_c).powi(2) + delta_h_sq/(s_h*s_h)).sqrt()
+}
+
+#[cfg(test)]
+mod tests
 {
-    let mut best_name = String::default();
-    let mut best_score = 0_f32;
-    let mut reader = Reader::from_path("src/data/color_names.csv").unwrap();
+    extern crate test;
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
 
-    for result in reader.records()
-    {
-        let result = result.unwrap();
-        let name = &result[0];
-        let l: f32 = result[1].parse().unwrap();
-        let a: f32 = result[2].parse().unwrap();
-        let b: f32 = result[3].parse().unwrap();
-        let lab = Lab::new(l,a,b);
-        let score = lab.similarity_to(&color);
-        if score > best_score
+    fn random_lab(rng: &mut impl Rng) -> Lab
+    {
+        Lab::new(rng.gen_range(0_f32..100_f32), rng.gen_range(-125_f32..125_f32), rng.gen_range(-125_f32..125_f32))
+    }
+
+    #[test]
+    fn rgb_round_trip()
+    {
+        let lab = LabImageBuffer::from_lab(3, 2, &Lab::new(50., 20., -30.));
+        let back = LabImageBuffer::from_rgb_image_buffer(&lab.as_rgb_image_buffer());
+        assert!(back.as_raw().iter().zip(lab.as_raw()).all(|(a, b)| (a - b).abs() < 1e-2));
+
+        let laba = LabaImageBuffer::from_lab(3, 2, &Laba::new(50., 20., -30., 0.5));
+        let back = LabaImageBuffer::from_rgb_image_buffer(&laba.as_rgb_image_buffer());
+        for (x, y) in [(0, 0), (2, 1)]
         {
-            best_score = score;
-            best_name = name.to_string();
+            let (a, b) = (back.get_pixel(x, y), laba.get_pixel(x, y));
+            assert!((a.l - b.l).abs() < 1e-2 && (a.a - b.a).abs() < 1e-2 && (a.b - b.b).abs() < 1e-2 && a.alpha == b.alpha, "{a:?} {b:?}");
         }
+    }
+
+    #[test]
+    fn metrics_are_normalized()
+    {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let black = Lab::new(0., 0., 0.);
+        let white = Lab::new(100., 0., 0.);
+        for metric in ColorMetric::ALL
+        {
+            assert!(metric.difference(&white, &white).abs() < 1e-6, "{metric:?}");
+            assert!(metric.difference(&black, &white) > metric.difference(&black, &Lab::new(50., 0., 0.)), "{metric:?}");
+            for _ in 0..1000
+            {
+                let (a, b) = (random_lab(&mut rng), random_lab(&mut rng));
+                let diff = metric.difference(&a, &b);
+                assert!((0. ..=1.).contains(&diff), "{metric:?} {a:?} {b:?} {diff}");
+            }
+        }
+        //Lightness counts for more than color in the weighted metric
+        let lighter = Lab::new(60., 0., 0.);
+        let redder = Lab::new(50., 10., 0.);
+        let grey = Lab::new(50., 0., 0.);
+        assert!(ColorMetric::LuminanceWeighted.difference(&grey, &lighter) > ColorMetric::LuminanceWeighted.difference(&grey, &redder));
+    }
+
+    fn bench_metric(b: &mut test::Bencher, metric: ColorMetric)
+    {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let pairs: Vec<(Lab, Lab)> = (0..1024).map(|_| (random_lab(&mut rng), random_lab(&mut rng))).collect();
+        b.iter(|| pairs.iter().map(|(a, b)| metric.difference(a, b)).sum::<f32>());
+    }
 
+    #[bench]
+    fn difference_euclidean(b: &mut test::Bencher)
+    {
+        bench_metric(b, ColorMetric::Euclidean);
     }
-    return best_name
-}
\ No newline at end of file
+
+    #[bench]
+    fn difference_cie94(b: &mut test::Bencher)
+    {
+        bench_metric(b, ColorMetric::Cie94);
+    }
+
+    #[bench]
+    fn difference_ciede2000(b: &mut test::Bencher)
+    {
+        bench_metric(b, ColorMetric::Ciede2000);
+    }
+
+    #[bench]
+    fn difference_luminance_weighted(b: &mut test::Bencher)
+    {
+        bench_metric(b, ColorMetric::LuminanceWeighted);
+    }
+}
@@ -1,6 +1,5 @@
 use image::Rgba32FImage;
 use line_drawing::XiaolinWu;
-use lerp::Lerp;
 use palette::Lab;
 
 extern crate geo;
@@ -37,7 +36,8 @@ pub fn lr_steps(start : (f32, f32), end : (f32, f32), edge_weight : f32) -> Vec<
     steps
 }
 
-pub fn draw_line_lab(point_a: (f32, f32), point_b: (f32, f32), image: &mut Rgba32FImage, color: &Lab, alpha: f32)
+#[allow(dead_code)]
+pub fn draw_line_lab(point_a: (f32, f32), point_b: (f32, f32), image: &mut Rgba32FImage, color: &Lab, _alpha: f32)
 {
     let xiao = XiaolinWu::<f32, i32>::new(point_a, point_b);
     xiao.for_each(|((x,y), value)| 
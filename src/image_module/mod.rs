@@ -1,2 +1,5 @@
 pub mod lines;
-pub mod lab;
\ No newline at end of file
+pub mod lab;
+pub mod quantize;
+pub mod color_names;
+pub mod evaluation;
\ No newline at end of file
@@ -0,0 +1,133 @@
+use super::lab::LabImageBuffer;
+
+use palette::Lab;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+//Pixels sampled from the image, more do not change the palette noticeably
+const MAX_SAMPLES : usize = 20000;
+const MAX_ITERATIONS : usize = 50;
+
+/*The `count` most common colors of the image, found by k-means clustering in Lab space.
+    The background is a fixed extra cluster, so that no thread is spent on the color which is already there.
+    Colors are ordered by the number of pixels in their cluster, most first.
+ */
+pub fn kmeans_palette(image: &LabImageBuffer, count: usize, background: &Lab, seed: u64) -> Vec<Lab>
+{
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let (width, height) = image.dimensions();
+    let pixel_count = (width * height) as usize;
+    let samples: Vec<[f32; 3]> = if pixel_count <= MAX_SAMPLES
+    {
+        (0..pixel_count).map(|i| lab_array(&image.pixel_at(i as u32))).collect()
+    }
+    else
+    {
+        (0..MAX_SAMPLES).map(|_| lab_array(&image.pixel_at(rng.gen_range(0..pixel_count) as u32))).collect()
+    };
+    if samples.is_empty() || count == 0 {return Vec::new()};
+
+    //k-means++ initialisation, starting from the background
+    let mut centroids = vec![lab_array(background)];
+    while centroids.len() < count + 1
+    {
+        let distances: Vec<f32> = samples.iter().map(|s| nearest(&centroids, s).1).collect();
+        let total: f32 = distances.iter().sum();
+        if total == 0. {break};
+        let mut target = rng.gen_range(0. ..total);
+        let idx = distances.iter().position(|d| {target -= d; target < 0.}).unwrap_or(samples.len() - 1);
+        centroids.push(samples[idx]);
+    }
+
+    let mut sizes = vec![0; centroids.len()];
+    for _ in 0..MAX_ITERATIONS
+    {
+        let mut sums = vec![[0_f32; 3]; centroids.len()];
+        sizes = vec![0; centroids.len()];
+        for sample in samples.iter()
+        {
+            let (cluster, _) = nearest(&centroids, sample);
+            sizes[cluster] += 1;
+            for c in 0..3 {sums[cluster][c] += sample[c]};
+        }
+        let mut moved = false;
+        //The background centroid stays where it is
+        for cluster in 1..centroids.len()
+        {
+            if sizes[cluster] == 0 {continue};
+            let mean = sums[cluster].map(|s| s / sizes[cluster] as f32);
+            moved |= distance_sq(&mean, &centroids[cluster]) > 1e-4;
+            centroids[cluster] = mean;
+        }
+        if !moved {break};
+    }
+
+    let mut clusters: Vec<(usize, [f32; 3])> = sizes.into_iter().zip(centroids).skip(1).collect();
+    clusters.sort_by_key(|c| std::cmp::Reverse(c.0));
+    clusters.into_iter().map(|(_, c)| Lab::new(c[0], c[1], c[2])).collect()
+}
+
+//Index of the closest catalog color for every color, using each catalog color at most once while there are enough.
+//  The catalog must not be empty.
+pub fn closest_in_catalog(colors: &[Lab], catalog: &[Lab]) -> Vec<usize>
+{
+    let catalog: Vec<[f32; 3]> = catalog.iter().map(lab_array).collect();
+    let mut used = vec![false; catalog.len()];
+    colors.iter().map(|color|
+    {
+        let color = lab_array(color);
+        let all_used = used.iter().all(|u| *u);
+        let idx = (0..catalog.len()).filter(|&i| !used[i] || all_used)
+            .min_by(|&a, &b| distance_sq(&catalog[a], &color).total_cmp(&distance_sq(&catalog[b], &color)))
+            .unwrap();
+        used[idx] = true;
+        idx
+    }).collect()
+}
+
+fn lab_array(color: &Lab) -> [f32; 3]
+{
+    [color.l, color.a, color.b]
+}
+
+fn distance_sq(a: &[f32; 3], b: &[f32; 3]) -> f32
+{
+    (a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)
+}
+
+//Index of and squared distance to the closest centroid
+fn nearest(centroids: &[[f32; 3]], sample: &[f32; 3]) -> (usize, f32)
+{
+    centroids.iter().map(|c| distance_sq(c, sample)).enumerate()
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::image_module::lab::LabBuf;
+    use palette::Laba;
+
+    #[test]
+    fn finds_colors_other_than_background()
+    {
+        let white = Lab::new(100., 0., 0.);
+        let mut image = LabImageBuffer::from_lab(40, 40, &Laba::new(100., 0., 0., 1.));
+        for x in 0..40
+        {
+            for y in 0..10 {image.put_pixel(x, y, &Lab::new(50., 60., 40.))};
+            for y in 10..30 {image.put_pixel(x, y, &Lab::new(20., 0., -40.))};
+        }
+        let palette = kmeans_palette(&image, 2, &white, 1);
+        assert_eq!(palette.len(), 2);
+        //The larger cluster comes first
+        assert!((palette[0].l - 20.).abs() < 1e-3 && (palette[0].b + 40.).abs() < 1e-3, "{palette:?}");
+        assert!((palette[1].l - 50.).abs() < 1e-3 && (palette[1].a - 60.).abs() < 1e-3, "{palette:?}");
+        assert_eq!(palette, kmeans_palette(&image, 2, &white, 1));
+
+        let catalog = [Lab::new(25., 0., -35.), Lab::new(0., 0., 0.)];
+        assert_eq!(closest_in_catalog(&palette, &catalog), vec![0, 1]);
+    }
+}
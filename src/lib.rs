@@ -0,0 +1,6 @@
+#![cfg_attr(test, feature(test))]
+
+pub mod image_module;
+pub mod string_path;
+pub mod tri_vec;
+pub mod cli;
@@ -1,12 +1,55 @@
-#![feature(path_file_prefix)]
+use stringwind::cli;
+use clap::Parser;
+use log::{Log, Level, LevelFilter, Metadata, Record};
 
-mod image_module;
-mod string_path;
-mod tri_vec;
+//Prints the library's warnings and notes to stderr
+struct StderrLogger;
+
+impl Log for StderrLogger
+{
+    fn enabled(&self, metadata: &Metadata) -> bool
+    {
+        metadata.level() <= Level::Info
+    }
+
+    fn log(&self, record: &Record)
+    {
+        match record.level()
+        {
+            Level::Error => eprintln!("Error: {}", record.args()),
+            Level::Warn => eprintln!("Warning: {}", record.args()),
+            _ if self.enabled(record.metadata()) => eprintln!("{}", record.args()),
+            _ => ()
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER : StderrLogger = StderrLogger;
 
-#[show_image::main]
 pub fn main()
 {
-    let path = string_path::path_generation::generate_path("src/tests/settings.toml").unwrap();
-    path.save_visual().unwrap();
+    log::set_logger(&LOGGER).expect("no other logger is set");
+    log::set_max_level(LevelFilter::Info);
+    let command = cli::Cli::parse().command;
+    //The preview window has to be driven from the main thread, so only start the window context when asked to
+    if command.needs_window()
+    {
+        show_image::run_context(move || -> () {std::process::exit(run(command))});
+    }
+    std::process::exit(run(command));
+}
+
+fn run(command: cli::Command) -> i32
+{
+    match cli::run(command)
+    {
+        Ok(()) => 0,
+        Err(e) =>
+        {
+            eprintln!("Error: {}", e.message());
+            e.exit_code()
+        }
+    }
 }
\ No newline at end of file
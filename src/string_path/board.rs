@@ -0,0 +1,312 @@
+use super::string_path::PathStep;
+use super::string_setting::StringSettings;
+use crate::image_module::lab::{LabImageBuffer, LabBuf};
+
+use palette::{Lab, Laba, Mix};
+use serde::{Serialize, Deserialize};
+
+//Which way the thread is wound around a nail, as seen on the image
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WrapDirection
+{
+    #[default]
+    Clockwise,
+    CounterClockwise
+}
+
+impl WrapDirection
+{
+    pub fn reversed(&self) -> WrapDirection
+    {
+        match self
+        {
+            WrapDirection::Clockwise => WrapDirection::CounterClockwise,
+            WrapDirection::CounterClockwise => WrapDirection::Clockwise
+        }
+    }
+
+    //Side of the nail the thread passes, relative to its direction of travel: 1 to the right as seen on the image, -1 to the left
+    fn side(&self) -> f32
+    {
+        match self
+        {
+            WrapDirection::Clockwise => -1.,
+            WrapDirection::CounterClockwise => 1.
+        }
+    }
+}
+
+/*Ends of the string between two nails of the given radius, where it touches the nails given the way it is wound around
+    each. Strings wound the same way around both nails run parallel to the line between them, strings wound opposite ways cross it.
+ */
+pub fn tangent_chord(from: (f32, f32), to: (f32, f32), from_wrap: WrapDirection, to_wrap: WrapDirection, radius: f32) -> ((f32, f32), (f32, f32))
+{
+    let d = (to.0 - from.0, to.1 - from.1);
+    let length = (d.0*d.0 + d.1*d.1).sqrt();
+    let (from_radius, to_radius) = (from_wrap.side() * radius, to_wrap.side() * radius);
+    let sin = (from_radius - to_radius) / length;
+    //Nails touching each other, or no nails at all
+    if radius <= 0. || sin.is_nan() || sin.abs() >= 1. {return (from, to)};
+    let cos = (1. - sin*sin).sqrt();
+    let u = (d.0 / length, d.1 / length);
+    let normal = (-u.1 * cos + u.0 * sin, u.0 * cos + u.1 * sin);
+    (
+        (from.0 + normal.0 * from_radius, from.1 + normal.1 * from_radius),
+        (to.0 + normal.0 * to_radius, to.1 + normal.1 * to_radius)
+    )
+}
+
+/*The strings a pair of pins can be connected by, one for each way the thread can be wound around the two nails. The sides
+    of a pair are numbered by the ways its strings are wound going from the lower pin to the higher one. Point-like nails,
+    without a board to size them, connect every pair by the one string between their centers.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChordSides
+{
+    nail_radius : f32 //In pixels of the input image
+}
+
+impl ChordSides
+{
+    pub fn new(nail_radius: f32) -> ChordSides
+    {
+        ChordSides {nail_radius}
+    }
+
+    pub fn count(&self) -> usize
+    {
+        if self.nail_radius > 0. {4} else {1}
+    }
+
+    //Side of the pair's strings the string from one pin to the other runs along, wound the given ways around each
+    pub fn side(&self, from_idx: usize, to_idx: usize, from_wrap: WrapDirection, to_wrap: WrapDirection) -> usize
+    {
+        if self.count() == 1 {return 0};
+        //Going the other way round the same string reverses the way it turns around both nails
+        let (low_wrap, high_wrap) = if from_idx < to_idx {(from_wrap, to_wrap)} else {(to_wrap.reversed(), from_wrap.reversed())};
+        let index = |wrap: WrapDirection| (wrap == WrapDirection::CounterClockwise) as usize;
+        index(low_wrap) * 2 + index(high_wrap)
+    }
+
+    //Ends of the pair's string on the given side, starting at the lower pin
+    pub fn ends(&self, pins: &[(f32, f32)], pair: (usize, usize), side: usize) -> ((f32, f32), (f32, f32))
+    {
+        let (low, high) = (pair.0.min(pair.1), pair.0.max(pair.1));
+        let wrap = |index: usize| if index == 0 {WrapDirection::Clockwise} else {WrapDirection::CounterClockwise};
+        self.string_ends(pins[low], pins[high], wrap(side / 2), wrap(side % 2))
+    }
+
+    //Ends of the string from one pin to another, wound the given ways around each
+    pub fn string_ends(&self, from: (f32, f32), to: (f32, f32), from_wrap: WrapDirection, to_wrap: WrapDirection) -> ((f32, f32), (f32, f32))
+    {
+        tangent_chord(from, to, from_wrap, to_wrap, self.nail_radius)
+    }
+}
+
+//The physical board the path is wound on, mapping pixels of the input image to millimetres
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Board
+{
+    pub mm_per_pixel : f32, //Millimetres per pixel of the input image
+    pub nail_diameter_mm : f32,
+    pub thread_thickness_mm : f32,
+    pub thread_opacity : f32
+}
+
+impl Board
+{
+    //The board diameter spans the pins' extent, so the board is only known if its diameter is set
+    pub fn from_settings(settings: &StringSettings, pins: &[(f32, f32)]) -> Option<Board>
+    {
+        let board_diameter_mm = settings.board_diameter_mm?;
+        let extent = |coordinate: fn(&(f32, f32)) -> f32|
+        {
+            let values = pins.iter().map(coordinate);
+            values.clone().fold(f32::MIN, f32::max) - values.fold(f32::MAX, f32::min)
+        };
+        let extent = extent(|p| p.0).max(extent(|p| p.1));
+        Some(Board
+        {
+            mm_per_pixel: board_diameter_mm / extent,
+            nail_diameter_mm: settings.nail_diameter_mm,
+            thread_thickness_mm: settings.thread_thickness_mm,
+            thread_opacity: settings.thread_opacity
+        })
+    }
+
+    //How much a string covers a pixel of the input image it passes through
+    pub fn line_strength(&self) -> f32
+    {
+        (self.thread_thickness_mm / self.mm_per_pixel).min(1.) * self.thread_opacity
+    }
+
+    //Radius of the nails in pixels of the input image
+    pub fn nail_radius(&self) -> f32
+    {
+        self.nail_diameter_mm / self.mm_per_pixel / 2.
+    }
+
+    //Thread used by wrapping half a turn around a nail
+    pub fn wrap_length_mm(&self) -> f32
+    {
+        std::f32::consts::PI * self.nail_diameter_mm / 2.
+    }
+
+    //Thread used by a string between the given ends where it touches the nails, including its wrap around the last one
+    pub fn string_length_mm(&self, from: (f32, f32), to: (f32, f32)) -> f32
+    {
+        ((to.0 - from.0).powi(2) + (to.1 - from.1).powi(2)).sqrt() * self.mm_per_pixel + self.wrap_length_mm()
+    }
+
+    /*Draw the path as it would look wound on the board, in an image of the given dimensions.
+        Strings are drawn with their real thickness and opacity, then the nails on top.
+     */
+    pub fn render(&self, path: &[PathStep], pins: &[(f32, f32)], colors: &[Lab], background: &Lab, input_dimensions: (u32, u32), dimensions: (u32, u32)) -> LabImageBuffer
+    {
+        //Keep the board round if the output has a different aspect ratio, centering it instead
+        let scale = (dimensions.0 as f32 / input_dimensions.0 as f32).min(dimensions.1 as f32 / input_dimensions.1 as f32);
+        let offset = ((dimensions.0 as f32 - input_dimensions.0 as f32 * scale) / 2., (dimensions.1 as f32 - input_dimensions.1 as f32 * scale) / 2.);
+        let pixel_mm = self.mm_per_pixel / scale;
+        let pins: Vec<(f32, f32)> = pins.iter().map(|p| (p.0 * scale + offset.0, p.1 * scale + offset.1)).collect();
+        let mut image = LabImageBuffer::from_lab(dimensions.0, dimensions.1, &Laba::new(background.l, background.a, background.b, 1.));
+        let nail_radius = self.nail_diameter_mm / pixel_mm / 2.;
+        //Each color's thread starts tied to its first pin, leaving it as if wound the way it is wound around the next one
+        let mut wraps: Vec<Option<WrapDirection>> = vec![None; colors.len()];
+        for step in path
+        {
+            let from_wrap = wraps[step.color_idx].unwrap_or(step.wrap);
+            let (start, end) = tangent_chord(pins[step.from_idx], pins[step.to_idx], from_wrap, step.wrap, nail_radius);
+            draw_thick_line(&mut image, start, end, self.thread_thickness_mm / pixel_mm, &colors[step.color_idx], self.thread_opacity);
+            wraps[step.color_idx] = Some(step.wrap);
+        }
+        for &pin in pins.iter()
+        {
+            draw_disc(&mut image, pin, nail_radius, &Lab::new(30., 0., 0.));
+        }
+        image
+    }
+}
+
+//Output dimensions from the width and height settings, keeping the input's aspect ratio if only one is set
+pub fn output_dimensions(settings: &StringSettings, input_dimensions: (u32, u32)) -> (u32, u32)
+{
+    let aspect = input_dimensions.1 as f32 / input_dimensions.0 as f32;
+    match (settings.width, settings.height)
+    {
+        (Some(width), Some(height)) => (width, height),
+        (Some(width), None) => (width, (width as f32 * aspect).round() as u32),
+        (None, Some(height)) => ((height as f32 / aspect).round() as u32, height),
+        (None, None) => input_dimensions
+    }
+}
+
+//Anti-aliased line of the given width in pixels. Lines thinner than a pixel are drawn fainter instead.
+fn draw_thick_line(image: &mut LabImageBuffer, start: (f32, f32), end: (f32, f32), width: f32, color: &Lab, opacity: f32)
+{
+    let diff = (end.0 - start.0, end.1 - start.1);
+    let len_sq = diff.0*diff.0 + diff.1*diff.1;
+    if len_sq == 0. {return};
+    let half_width = width.max(1.) / 2.;
+    let strength = width.min(1.) * opacity;
+    let steep = diff.1.abs() > diff.0.abs();
+    //Walk along the major axis, covering the pixels within reach of the line across it
+    let (major_start, major_end, minor_start, slope) = if steep
+        {(start.1.min(end.1), start.1.max(end.1), if start.1 < end.1 {start.0} else {end.0}, diff.0 / diff.1)}
+    else
+        {(start.0.min(end.0), start.0.max(end.0), if start.0 < end.0 {start.1} else {end.1}, diff.1 / diff.0)};
+    let reach = (half_width + 1.) * (1. + slope*slope).sqrt();
+    let (width_px, height_px) = image.dimensions();
+    for major in (major_start - half_width).floor() as i64..=(major_end + half_width).ceil() as i64
+    {
+        let minor_center = minor_start + (major as f32 - major_start).clamp(0., major_end - major_start) * slope;
+        for minor in (minor_center - reach).floor() as i64..=(minor_center + reach).ceil() as i64
+        {
+            let (x, y) = if steep {(minor, major)} else {(major, minor)};
+            if x < 0 || y < 0 || x >= width_px as i64 || y >= height_px as i64 {continue};
+            let point = (x as f32, y as f32);
+            let t = (((point.0 - start.0) * diff.0 + (point.1 - start.1) * diff.1) / len_sq).clamp(0., 1.);
+            let closest = (start.0 + diff.0 * t, start.1 + diff.1 * t);
+            let distance = ((point.0 - closest.0).powi(2) + (point.1 - closest.1).powi(2)).sqrt();
+            let coverage = (half_width + 0.5 - distance).clamp(0., 1.);
+            if coverage > 0.
+            {
+                let pixel = image.get_pixel(x as u32, y as u32);
+                image.put_pixel(x as u32, y as u32, &pixel.mix(color, coverage * strength));
+            }
+        }
+    }
+}
+
+fn draw_disc(image: &mut LabImageBuffer, center: (f32, f32), radius: f32, color: &Lab)
+{
+    let (width, height) = image.dimensions();
+    let reach = radius.ceil() as i64 + 1;
+    for y in (center.1 as i64 - reach).max(0)..=(center.1 as i64 + reach).min(height as i64 - 1)
+    {
+        for x in (center.0 as i64 - reach).max(0)..=(center.0 as i64 + reach).min(width as i64 - 1)
+        {
+            let distance = ((x as f32 - center.0).powi(2) + (y as f32 - center.1).powi(2)).sqrt();
+            let coverage = (radius + 0.5 - distance).clamp(0., 1.);
+            if coverage > 0.
+            {
+                let pixel = image.get_pixel(x as u32, y as u32);
+                image.put_pixel(x as u32, y as u32, &pixel.mix(color, coverage));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn thread_thickness_scales_with_output()
+    {
+        let board = Board {mm_per_pixel: 1., nail_diameter_mm: 0., thread_thickness_mm: 4., thread_opacity: 1.};
+        let path = [PathStep {from_idx: 0, to_idx: 1, color_idx: 0, score: 0., wrap: WrapDirection::Clockwise}];
+        let pins = [(2., 10.), (18., 10.)];
+        let black = Lab::new(0., 0., 0.);
+        let white = Lab::new(100., 0., 0.);
+        //Width of the string across the given column, in pixels
+        let covered = |image: &LabImageBuffer, x: u32| (0..image.height()).map(|y| (100. - image.get_pixel(x, y).l) / 100.).sum::<f32>();
+        //4mm at 1mm per pixel, then at 2 output pixels per input pixel
+        assert!((covered(&board.render(&path, &pins, &[black], &white, (20, 20), (20, 20)), 10) - 4.).abs() < 1e-2);
+        assert!((covered(&board.render(&path, &pins, &[black], &white, (20, 20), (40, 40)), 20) - 8.).abs() < 1e-2);
+
+        let faint = Board {thread_opacity: 0.25, ..board};
+        let image = faint.render(&path, &pins, &[black], &white, (20, 20), (20, 20));
+        assert!((image.get_pixel(10, 10).l - 75.).abs() < 1e-3);
+    }
+
+    #[test]
+    fn chords_touch_the_nails()
+    {
+        let (cw, ccw) = (WrapDirection::Clockwise, WrapDirection::CounterClockwise);
+        let close = |a: (f32, f32), b: (f32, f32)| (a.0 - b.0).abs() < 1e-4 && (a.1 - b.1).abs() < 1e-4;
+        //Clockwise around both, passing above the nails when going right on the image
+        let (start, end) = tangent_chord((0., 0.), (10., 0.), cw, cw, 1.);
+        assert!(close(start, (0., -1.)) && close(end, (10., -1.)), "{start:?} {end:?}");
+        let (start, end) = tangent_chord((0., 0.), (10., 0.), ccw, ccw, 1.);
+        assert!(close(start, (0., 1.)) && close(end, (10., 1.)), "{start:?} {end:?}");
+        //Opposite ways, crossing the line between the nails at its middle while touching both
+        let (start, end) = tangent_chord((0., 0.), (10., 0.), cw, ccw, 1.);
+        assert!(close(((start.0 + end.0) / 2., (start.1 + end.1) / 2.), (5., 0.)), "{start:?} {end:?}");
+        assert!((start.0.powi(2) + start.1.powi(2) - 1.).abs() < 1e-4 && start.1 < 0. && end.1 > 0.);
+        assert_eq!(tangent_chord((0., 0.), (10., 0.), cw, ccw, 0.), ((0., 0.), (10., 0.)));
+
+        //The same string, whichever pin it is wound from
+        let sides = ChordSides::new(1.);
+        let pins = [(0., 0.), (10., 0.)];
+        assert_eq!(sides.side(0, 1, cw, ccw), sides.side(1, 0, cw, ccw));
+        assert_eq!(sides.side(0, 1, cw, cw), sides.side(1, 0, ccw, ccw));
+        assert_ne!(sides.side(0, 1, cw, cw), sides.side(0, 1, ccw, ccw));
+        assert_eq!(sides.ends(&pins, (1, 0), sides.side(1, 0, ccw, ccw)), tangent_chord(pins[0], pins[1], cw, cw, 1.));
+        let (start, end) = sides.ends(&pins, (0, 1), sides.side(1, 0, cw, ccw));
+        assert!(close(start, tangent_chord(pins[1], pins[0], cw, ccw, 1.).1) && close(end, tangent_chord(pins[1], pins[0], cw, ccw, 1.).0));
+        let point_like = ChordSides::new(0.);
+        assert_eq!((point_like.count(), point_like.side(1, 0, ccw, cw)), (1, 0));
+    }
+}
@@ -0,0 +1,52 @@
+use super::string_path::{PathStep, StringCombo};
+use crate::image_module::evaluation::Evaluation;
+use crate::tri_vec::TriVec;
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use rand_chacha::ChaCha8Rng;
+use serde::{Serialize, Deserialize};
+
+//Full state of a partially generated path, enough to continue generating it
+#[derive(Serialize, Deserialize)]
+pub struct Checkpoint
+{
+    pub version : u32,
+    pub settings_hash : u64, //StringSettings::path_hash of the settings the path was generated with
+    pub seed : u64, //Drawn at random if the settings did not give one
+    pub path : Vec<PathStep>,
+    pub cur_step : usize,
+    pub cur_idxs : Vec<usize>,
+    pub cur_scores : Vec<f32>,
+    pub rng : ChaCha8Rng,
+    pub combo_scores : TriVec<Vec<StringCombo>>, //Cached line scores, which are not recomputed exactly on resume
+    pub dimensions : (u32, u32),
+    pub strings_drawn : Vec<f32>, //Raw Lab values of the drawn strings
+    pub quality_log : Vec<Evaluation>,
+    pub ended_colors : Vec<bool>
+}
+
+impl Checkpoint
+{
+    pub const VERSION : u32 = 5;
+
+    //Write to a temporary file first, so that an interrupted save never replaces the previous checkpoint
+    pub fn save(&self, path: &str) -> Result<(), String>
+    {
+        let temp_path = format!("{path}.tmp");
+        let file = File::create(&temp_path).map_err(|e| format!("{temp_path}: {e}"))?;
+        bincode::serialize_into(BufWriter::new(file), self).map_err(|e| format!("{temp_path}: {e}"))?;
+        std::fs::rename(&temp_path, path).map_err(|e| format!("{path}: {e}"))
+    }
+
+    pub fn load(path: &str) -> Result<Checkpoint, String>
+    {
+        let file = File::open(path).map_err(|e| format!("{path}: {e}"))?;
+        let checkpoint: Checkpoint = bincode::deserialize_from(BufReader::new(file)).map_err(|e| format!("{path}: {e}"))?;
+        if checkpoint.version != Checkpoint::VERSION
+        {
+            return Err(format!("{path}: unsupported checkpoint version {}", checkpoint.version));
+        }
+        Ok(checkpoint)
+    }
+}
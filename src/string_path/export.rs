@@ -0,0 +1,187 @@
+use super::string_path::PathStep;
+use super::string_setting::StringSettings;
+use super::thread_catalog::Thread;
+use super::board::WrapDirection;
+
+use std::fs::File;
+use palette::{Lab, Srgb, IntoColor};
+use serde::{Serialize, Deserialize};
+
+//Machine-readable description of a finished path, in the order the strings should be wound
+#[derive(Serialize, Deserialize)]
+pub struct WindingInstructions
+{
+    pub version : u32,
+    pub image_dimensions : (u32, u32),
+    pub pin_radius : f32,
+    pub pins : Vec<PinPosition>, //Pin positions in pixels of the input image
+    pub colors : Vec<ColorThread>, //In the order they are wound if the colors are layered
+    pub step_count : usize,
+    #[serde(default)]
+    pub seed : u64,
+    //Color indices from the bottom layer to the top one, when colors are wound one after the other
+    #[serde(default)]
+    pub layer_order : Option<Vec<usize>>,
+    pub settings : StringSettings //Settings used to generate the path
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PinPosition
+{
+    pub index : usize,
+    pub x : f32,
+    pub y : f32
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ColorThread
+{
+    pub index : usize,
+    pub name : String,
+    pub lab : [f32; 3],
+    pub srgb : [f32; 3],
+    #[serde(default)]
+    pub thread : Option<Thread>, //Product to buy, if the colors were chosen from a thread catalog
+    pub steps : Vec<WindingStep>
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct WindingStep
+{
+    pub step : usize, //Position of this step in the full, interleaved path
+    pub from_pin : usize,
+    pub to_pin : usize,
+    pub score : f32,
+    #[serde(default)]
+    pub wrap : WrapDirection //Way the thread is wound around to_pin
+}
+
+//One line of the CSV export
+#[derive(Serialize)]
+struct WindingRow<'a>
+{
+    step : usize,
+    color_idx : usize,
+    color_name : &'a str,
+    thread_code : &'a str,
+    from_pin : usize,
+    to_pin : usize,
+    wrap : WrapDirection,
+    score : f32
+}
+
+impl ColorThread
+{
+    pub fn new(index: usize, color: &Lab, name: String, thread: Option<Thread>, steps: Vec<WindingStep>) -> ColorThread
+    {
+        let srgb: Srgb = (*color).into_color();
+        ColorThread
+        {
+            index,
+            name,
+            lab: [color.l, color.a, color.b],
+            srgb: [srgb.red, srgb.green, srgb.blue],
+            thread,
+            steps
+        }
+    }
+}
+
+impl WindingInstructions
+{
+    pub const VERSION : u32 = 1;
+
+    pub fn save_json(&self, path: &str) -> Result<(), String>
+    {
+        let file = File::create(path).map_err(|e| format!("{path}: {e}"))?;
+        serde_json::to_writer_pretty(file, self).map_err(|e| format!("{path}: {e}"))
+    }
+
+    //Write one row per step, grouped by color and ordered by step within each color
+    pub fn save_csv(&self, path: &str) -> Result<(), String>
+    {
+        let mut writer = csv::Writer::from_path(path).map_err(|e| format!("{path}: {e}"))?;
+        for color in self.colors.iter()
+        {
+            for step in color.steps.iter()
+            {
+                writer.serialize(WindingRow
+                {
+                    step: step.step,
+                    color_idx: color.index,
+                    color_name: &color.name,
+                    thread_code: color.thread.as_ref().map_or("", |t| t.code.as_str()),
+                    from_pin: step.from_pin,
+                    to_pin: step.to_pin,
+                    wrap: step.wrap,
+                    score: step.score
+                }).map_err(|e| format!("{path}: {e}"))?;
+            }
+        }
+        writer.flush().map_err(|e| format!("{path}: {e}"))
+    }
+
+    pub fn load_json(path: &str) -> Result<WindingInstructions, String>
+    {
+        let file = File::open(path).map_err(|e| format!("{path}: {e}"))?;
+        let instructions: WindingInstructions = serde_json::from_reader(file).map_err(|e| format!("{path}: {e}"))?;
+        if instructions.version != WindingInstructions::VERSION
+        {
+            return Err(format!("{path}: unsupported instruction version {}", instructions.version));
+        }
+        Ok(instructions)
+    }
+
+    //Every step of every color, back in the order they were generated
+    pub fn path_steps(&self) -> Vec<PathStep>
+    {
+        let mut steps: Vec<(usize, PathStep)> = self.colors.iter()
+            .flat_map(|color| color.steps.iter().map(|s| (s.step, PathStep
+            {
+                from_idx: s.from_pin,
+                to_idx: s.to_pin,
+                color_idx: color.index,
+                score: s.score,
+                wrap: s.wrap
+            })))
+            .collect();
+        steps.sort_by_key(|(step, _)| *step);
+        steps.into_iter().map(|(_, step)| step).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::string_path::string_path::{StringPath, tests::run_to_end};
+
+    #[test]
+    fn instructions_round_trip()
+    {
+        let sp = run_to_end("export", "seed = 5\nboard_diameter_mm = 100\nnail_diameter_mm = 3");
+        let dir = std::env::temp_dir().join("stringwind_export");
+        let (json, csv) = (dir.join("path.json"), dir.join("path.csv"));
+        let instructions = sp.to_instructions().unwrap();
+        instructions.save_json(json.to_str().unwrap()).unwrap();
+        instructions.save_csv(csv.to_str().unwrap()).unwrap();
+
+        let loaded = WindingInstructions::load_json(json.to_str().unwrap()).unwrap();
+        assert_eq!(loaded.step_count, sp.path.len());
+        let replayed = StringPath::from_instructions(&loaded).unwrap();
+        assert_eq!(replayed.path, sp.path);
+        assert_eq!(replayed.pin_positions, sp.pin_positions);
+        assert_eq!(replayed.strings_drawn.as_raw(), sp.strings_drawn.as_raw());
+        let again = replayed.to_instructions().unwrap();
+        let colors = |i: &WindingInstructions| i.colors.iter().map(|c| (c.index, c.name.clone(), c.lab, c.steps.len())).collect::<Vec<_>>();
+        assert_eq!(colors(&again), colors(&instructions));
+        let pins = |i: &WindingInstructions| i.pins.iter().map(|p| (p.index, p.x, p.y)).collect::<Vec<_>>();
+        assert_eq!(pins(&again), pins(&instructions));
+        assert_eq!((again.image_dimensions, again.pin_radius), (instructions.image_dimensions, instructions.pin_radius));
+
+        let mut reader = csv::Reader::from_path(&csv).unwrap();
+        let header: Vec<String> = reader.headers().unwrap().iter().map(str::to_string).collect();
+        assert_eq!(header, ["step", "color_idx", "color_name", "thread_code", "from_pin", "to_pin", "wrap", "score"]);
+        assert_eq!(reader.records().count(), sp.path.len());
+    }
+}
@@ -0,0 +1,175 @@
+use crate::image_module::lab::{LabImageBuffer, LabBuf, ColorMetric};
+
+use line_drawing::XiaolinWu;
+use palette::{Lab, Mix};
+use rayon::prelude::*;
+
+//Every order is tried up to this many colors, more colors are reordered by swapping pairs instead
+const MAX_EXHAUSTIVE_COLORS : usize = 6;
+//Pixels compared when trying an order, larger images are sampled
+const ORDER_SAMPLE_PIXELS : usize = 1 << 16;
+
+/*The strings of each color as a layer over the background, the first color wound at the bottom.
+    Each color only covers a fraction of every pixel, so the drawn image can be recomposited in any order.
+ */
+pub struct Layers
+{
+    order : Vec<usize>, //Color indices, from the bottom layer to the top one
+    coverage : Vec<Vec<f32>>, //Fraction of each pixel covered by each color, row by row
+    colors : Vec<Lab>,
+    background : Lab,
+    width : u32
+}
+
+impl Layers
+{
+    pub fn new(order: Vec<usize>, colors: &[Lab], background: &Lab, dimensions: (u32, u32)) -> Layers
+    {
+        let pixel_count = (dimensions.0 * dimensions.1) as usize;
+        Layers
+        {
+            order,
+            coverage: vec![vec![0.; pixel_count]; colors.len()],
+            colors: colors.to_vec(),
+            background: *background,
+            width: dimensions.0
+        }
+    }
+
+    pub fn order(&self) -> &[usize]
+    {
+        &self.order
+    }
+
+    //Position of the color's layer, 0 for the bottom one
+    pub fn rank(&self, color_idx: usize) -> usize
+    {
+        self.order.iter().position(|&c| c == color_idx).unwrap()
+    }
+
+    //Color of the pixel at the given index, optionally with one more string of the given color and strength over its layer
+    pub fn composite(&self, idx: u32, extra: Option<(usize, f32)>) -> Lab
+    {
+        self.composite_in(&self.order, idx as usize, extra)
+    }
+
+    fn composite_in(&self, order: &[usize], idx: usize, extra: Option<(usize, f32)>) -> Lab
+    {
+        order.iter().fold(self.background, |below, &color_idx|
+        {
+            let coverage = match extra
+            {
+                Some((extra_idx, strength)) if extra_idx == color_idx => 1. - (1. - self.coverage[color_idx][idx]) * (1. - strength),
+                _ => self.coverage[color_idx][idx]
+            };
+            below.mix(&self.colors[color_idx], coverage)
+        })
+    }
+
+    //Add a string to its color's layer, updating the pixels it covers in the composited image
+    pub fn draw_line(&mut self, image: &mut LabImageBuffer, start: (f32, f32), end: (f32, f32), color_idx: usize, opacity: f32)
+    {
+        for ((x, y), weight) in XiaolinWu::<f32, i32>::new(start, end)
+        {
+            let idx = y as usize * self.width as usize + x as usize;
+            let coverage = &mut self.coverage[color_idx][idx];
+            *coverage = 1. - (1. - *coverage) * (1. - weight * opacity);
+            image.put_pixel(x as u32, y as u32, &self.composite_in(&self.order, idx, None));
+        }
+    }
+
+    //Stack the layers in the given order, recompositing the whole image
+    pub fn set_order(&mut self, order: Vec<usize>, image: &mut LabImageBuffer)
+    {
+        self.order = order;
+        let (width, height) = image.dimensions();
+        let raw = (0..(width * height) as usize).into_par_iter()
+            .flat_map_iter(|idx|
+            {
+                let color = self.composite_in(&self.order, idx, None);
+                [color.l, color.a, color.b]
+            })
+            .collect();
+        *image = LabImageBuffer::from_raw(width, height, raw).unwrap();
+    }
+
+    //The order whose composite differs least from the target image under the given metric
+    pub fn best_order(&self, target: &LabImageBuffer, metric: ColorMetric) -> Vec<usize>
+    {
+        let pixel_count = self.coverage.first().map_or(0, |c| c.len());
+        let stride = pixel_count.div_ceil(ORDER_SAMPLE_PIXELS).max(1);
+        let error = |order: &[usize]| -> f32
+        {
+            (0..pixel_count).into_par_iter().step_by(stride)
+                .map(|idx| metric.difference(&self.composite_in(order, idx, None), &target.pixel_at(idx as u32)))
+                .sum()
+        };
+        if self.order.len() <= MAX_EXHAUSTIVE_COLORS
+        {
+            return permutations(&self.order).into_iter()
+                .map(|order| (error(&order), order))
+                .min_by(|a, b| a.0.total_cmp(&b.0))
+                .map_or_else(Vec::new, |(_, order)| order);
+        }
+        //Swap pairs of layers for as long as that improves the composite
+        let mut best = self.order.clone();
+        let mut best_error = error(&best);
+        let count = best.len();
+        let mut improved = true;
+        while improved
+        {
+            improved = false;
+            for (a, b) in (0..count).flat_map(|a| (a+1..count).map(move |b| (a, b)))
+            {
+                let mut candidate = best.clone();
+                candidate.swap(a, b);
+                let candidate_error = error(&candidate);
+                if candidate_error < best_error
+                {
+                    (best, best_error, improved) = (candidate, candidate_error, true);
+                }
+            }
+        }
+        best
+    }
+}
+
+//Every ordering of the given items
+fn permutations(items: &[usize]) -> Vec<Vec<usize>>
+{
+    if items.len() <= 1 {return vec![items.to_vec()]};
+    (0..items.len()).flat_map(|first|
+    {
+        let rest: Vec<usize> = items.iter().enumerate().filter(|(i, _)| *i != first).map(|(_, item)| *item).collect();
+        permutations(&rest).into_iter().map(move |mut tail| {tail.insert(0, items[first]); tail})
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn top_layer_covers_lower_ones()
+    {
+        let (black, red) = (Lab::new(0., 0., 0.), Lab::new(50., 70., 50.));
+        let white = Lab::new(100., 0., 0.);
+        let mut image = LabImageBuffer::from_lab(20, 20, &white);
+        let mut layers = Layers::new(vec![0, 1], &[black, red], &white, (20, 20));
+        //Red is drawn first, yet black ends up under it where they cross
+        layers.draw_line(&mut image, (10., 0.), (10., 19.), 1, 1.);
+        layers.draw_line(&mut image, (0., 5.), (19., 5.), 0, 1.);
+        assert_eq!(image.get_pixel(10, 5), red);
+        assert_eq!(image.get_pixel(3, 5), black);
+        assert_eq!(layers.composite(5 * 20 + 3, Some((1, 0.5))), black.mix(&red, 0.5));
+
+        layers.set_order(vec![1, 0], &mut image);
+        assert_eq!(image.get_pixel(10, 5), black);
+        assert_eq!(permutations(&[0, 1, 2]).len(), 6);
+        //Only red is wanted where the strings cross
+        let mut target = LabImageBuffer::from_lab(20, 20, &white);
+        target.put_pixel(10, 5, &red);
+        assert_eq!(layers.best_order(&target, ColorMetric::Euclidean), vec![0, 1]);
+    }
+}
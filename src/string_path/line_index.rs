@@ -0,0 +1,128 @@
+use super::board::ChordSides;
+
+use line_drawing::XiaolinWu;
+
+//Coarse grid over the image listing, for every cell, the pin pairs whose score on any side reads pixels in that cell.
+//  After a string is drawn, only the pairs listed in the cells it covers can have a different score.
+pub struct LineIndex
+{
+    cell_size : i32,
+    columns : usize,
+    rows : usize,
+    cells : Vec<Vec<u32>>, //Ids of the pin pairs touching each cell
+    pairs : Vec<(usize, usize)>, //Pin pair of each id
+    stamps : Vec<u32>, //Query in which each pair was last returned, so that every pair is returned once per query
+    query : u32
+}
+
+impl LineIndex
+{
+    //Cells per side of the grid, at most
+    const MAX_CELLS : u32 = 256;
+
+    pub fn new(pins: &[(f32, f32)], dimensions: (u32, u32), pairs: Vec<(usize, usize)>, sides: ChordSides) -> LineIndex
+    {
+        let cell_size = (dimensions.0.max(dimensions.1) / LineIndex::MAX_CELLS).max(4) as i32;
+        let columns = (dimensions.0 as i32 / cell_size + 1) as usize;
+        let rows = (dimensions.1 as i32 / cell_size + 1) as usize;
+        let mut index = LineIndex
+        {
+            cell_size,
+            columns,
+            rows,
+            cells: vec![Vec::new(); columns * rows],
+            stamps: vec![0; pairs.len()],
+            pairs: Vec::new(),
+            query: 0
+        };
+        let mut touched = Vec::new();
+        for (id, &pair) in pairs.iter().enumerate()
+        {
+            touched.clear();
+            let strings = (0..sides.count()).map(|side| sides.ends(pins, pair, side));
+            for ((x, y), _) in strings.flat_map(|(start, end)| XiaolinWu::<f32, i32>::new(start, end))
+            {
+                //Scores also read the pixels to the left and right of the line
+                for cy in index.cell(y - 1)..=index.cell(y + 1)
+                {
+                    for cx in index.cell(x - 1)..=index.cell(x + 1)
+                    {
+                        let cell = cy * columns + cx;
+                        if touched.last() != Some(&cell) {touched.push(cell)};
+                    }
+                }
+            }
+            touched.sort_unstable();
+            touched.dedup();
+            for &cell in touched.iter()
+            {
+                index.cells[cell].push(id as u32);
+            }
+        }
+        index.pairs = pairs;
+        index
+    }
+
+    fn cell(&self, coordinate: i32) -> usize
+    {
+        (coordinate.max(0) / self.cell_size) as usize
+    }
+
+    //Every indexed pair which reads a pixel that drawing a string between the given points changes
+    pub fn affected(&mut self, from: (f32, f32), to: (f32, f32)) -> Vec<(usize, usize)>
+    {
+        self.query += 1;
+        let mut affected = Vec::new();
+        let mut last_cell = usize::MAX;
+        for ((x, y), _) in XiaolinWu::<f32, i32>::new(from, to)
+        {
+            let cell = self.cell(y).min(self.rows - 1) * self.columns + self.cell(x).min(self.columns - 1);
+            if cell == last_cell {continue};
+            last_cell = cell;
+            for &id in self.cells[cell].iter()
+            {
+                if self.stamps[id as usize] != self.query
+                {
+                    self.stamps[id as usize] = self.query;
+                    affected.push(self.pairs[id as usize]);
+                }
+            }
+        }
+        affected
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::string_path::pin_layout::PinLayout;
+
+    #[test]
+    fn crossing_lines_are_affected()
+    {
+        let pins = PinLayout::Circle {pin_count: 40, radius: 0.9}.positions((200, 200)).unwrap();
+        let pairs: Vec<(usize, usize)> = (0..40).flat_map(|x| (x+1..40).map(move |y| (x, y))).collect();
+        let mut index = LineIndex::new(&pins, (200, 200), pairs, ChordSides::new(0.));
+        let affected = index.affected(pins[0], pins[20]);
+        //Every chord with one pin on each side of the drawn one crosses it
+        for x in 1..20
+        {
+            for y in 21..40
+            {
+                assert!(affected.contains(&(x, y)), "{x}-{y}");
+            }
+        }
+        //A short chord far away from the drawn one
+        assert!(!affected.contains(&(9, 11)));
+        assert_eq!(index.affected(pins[0], pins[20]).len(), affected.len());
+
+        //A short string over one side of the pair's strings around thick nails, away from the line between the nails' centers
+        let sides = ChordSides::new(10.);
+        let (start, end) = sides.ends(&pins, (5, 15), 3);
+        let middle = ((start.0 + end.0) / 2., (start.1 + end.1) / 2.);
+        let short = ((middle.0 - 2., middle.1), (middle.0 + 2., middle.1));
+        assert_eq!(LineIndex::new(&pins, (200, 200), vec![(5, 15)], sides).affected(short.0, short.1), vec![(5, 15)]);
+        assert!(LineIndex::new(&pins, (200, 200), vec![(5, 15)], ChordSides::new(0.)).affected(short.0, short.1).is_empty());
+    }
+}
@@ -0,0 +1,102 @@
+use crate::tri_vec::TriVec;
+use super::board::ChordSides;
+
+use line_drawing::XiaolinWu;
+use rayon::prelude::*;
+
+//A pixel covered by a line, with the pixels to its left and right. Pixels are indices into the image's pixels.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LinePixel
+{
+    pub pixel : u32,
+    pub weight : f32,
+    pub left : u32,
+    pub right : u32
+}
+
+//Pixels of the line between two points in an image of the given dimensions
+pub fn line_pixels(start: (f32, f32), end: (f32, f32), dimensions: (u32, u32)) -> impl Iterator<Item = LinePixel>
+{
+    let diff = (end.0 - start.0, end.1 - start.1);
+    let len = (diff.0*diff.0 + diff.1*diff.1).sqrt();
+    let offset = ((diff.0 / len).round() as i32,  (diff.1 / len).round() as i32);
+    let (width, height) = (dimensions.0 as i32, dimensions.1 as i32);
+    let index = move |x: i32, y: i32| (y.clamp(0, height - 1) * width + x.clamp(0, width - 1)) as u32;
+    XiaolinWu::<f32, i32>::new(start, end).map(move |((x, y), weight)| LinePixel
+    {
+        pixel: index(x, y),
+        weight,
+        left: index(x + offset.1, y - offset.0),
+        right: index(x - offset.1, y + offset.0)
+    })
+}
+
+//Pixels of every side of every allowed line, rasterized once instead of on every score calculation
+pub struct LineTable
+{
+    lines : TriVec<Vec<Vec<LinePixel>>> //Per pair, the pixels of each side
+}
+
+impl LineTable
+{
+    //Approximate size of the table in bytes. Xiaolin Wu lines cover two pixels per step along their major axis.
+    pub fn estimate_bytes(pins: &[(f32, f32)], pairs: &[(usize, usize)], sides: ChordSides) -> usize
+    {
+        let pixels: usize = pairs.iter()
+            .map(|&(a, b)|
+            {
+                let major = (pins[a].0 - pins[b].0).abs().max((pins[a].1 - pins[b].1).abs());
+                2 * (major.ceil() as usize + 2)
+            })
+            .sum();
+        let entries = pins.len() * (pins.len() + 1) / 2;
+        sides.count() * (pixels * std::mem::size_of::<LinePixel>() + pairs.len() * std::mem::size_of::<Vec<LinePixel>>())
+            + entries * std::mem::size_of::<Vec<Vec<LinePixel>>>()
+    }
+
+    pub fn new(pins: &[(f32, f32)], dimensions: (u32, u32), pairs: &[(usize, usize)], sides: ChordSides) -> LineTable
+    {
+        let rasterized: Vec<Vec<Vec<LinePixel>>> = pairs.par_iter()
+            .map(|&pair| (0..sides.count()).map(|side|
+            {
+                let (start, end) = sides.ends(pins, pair, side);
+                line_pixels(start, end, dimensions).collect()
+            }).collect())
+            .collect();
+        let mut lines = TriVec::new(pins.len(), &Vec::new());
+        for (&(a, b), pixels) in pairs.iter().zip(rasterized)
+        {
+            lines.set(a, b, pixels);
+        }
+        LineTable {lines}
+    }
+
+    pub fn get(&self, a: usize, b: usize, side: usize) -> &[LinePixel]
+    {
+        &self.lines.get(a, b)[side]
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn neighbours_are_across_the_line()
+    {
+        let pixels: Vec<LinePixel> = line_pixels((2., 5.), (8., 5.), (10, 10)).collect();
+        let on_line = pixels.iter().find(|p| p.pixel == 5 * 10 + 4).unwrap();
+        assert_eq!((on_line.left, on_line.right), (4 * 10 + 4, 6 * 10 + 4));
+        //Neighbours of pixels on the border stay inside the image
+        assert!(line_pixels((0., 0.), (9., 0.), (10, 10)).all(|p| p.left < 100 && p.right < 100));
+        let pins = [(2., 5.), (8., 5.)];
+        assert!(LineTable::estimate_bytes(&pins, &[(0, 1)], ChordSides::new(0.)) >= pixels.len() * std::mem::size_of::<LinePixel>());
+
+        //Strings passing above and below the nails
+        let table = LineTable::new(&pins, (10, 10), &[(0, 1)], ChordSides::new(1.));
+        let rows = |side: usize| table.get(1, 0, side).iter().map(|p| p.pixel / 10).collect::<Vec<_>>();
+        assert!(rows(0).iter().all(|&row| row <= 5) && rows(3).iter().all(|&row| row >= 5), "{:?} {:?}", rows(0), rows(3));
+        assert!(LineTable::estimate_bytes(&pins, &[(0, 1)], ChordSides::new(1.)) >= 4 * pixels.len() * std::mem::size_of::<LinePixel>());
+    }
+}
@@ -1,3 +1,16 @@
-mod string_path;
+#[allow(clippy::module_inception)]
+pub mod string_path;
 pub mod path_generation;
-mod string_setting;
+pub mod string_setting;
+pub mod export;
+pub mod pin_layout;
+pub mod checkpoint;
+pub mod line_index;
+pub mod line_table;
+pub mod board;
+pub mod report;
+pub mod thread_catalog;
+pub mod layers;
+pub mod refine;
+pub mod scorer;
+pub mod sample;
\ No newline at end of file
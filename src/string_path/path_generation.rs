@@ -1,19 +1,13 @@
-use crate::image_module::lines::draw_line_lab;
 use super::string_setting::*;
 use super::string_path::*;
-use super::super::tri_vec::*;
-use super::super::image_module::lab::{LabImageBuffer, LabaImageBuffer, LabBuf};
+use super::super::image_module::lab::LabBuf;
 
-use geo::{Line, coord, algorithm::line_intersection::line_intersection, LineIntersection};
-use image:: {RgbaImage, Rgba, Rgba32FImage, ImageResult, EncodableLayout, DynamicImage};
-use palette::{Lab, Laba, Mix};
-use std::path::Path;
-use line_drawing::XiaolinWu;
+use image:: {EncodableLayout, DynamicImage, RgbImage, Rgb};
 use show_image::{ImageView, ImageInfo, create_window};
-use rand::distributions::{WeightedIndex,Distribution};
 
 
 
+#[allow(dead_code)]
 trait StringPathTests
 {   
     fn fill_unique_pixels(&self);
@@ -86,26 +80,80 @@ impl StringPathTests for StringPath
 }
 */
 
-pub fn generate_path(settings_path: &'static str) -> Result<StringPath, String>
+pub fn generate_path(settings: StringSettings, preview: bool) -> Result<StringPath, String>
 {
-    let settings = read_string_settings(settings_path).map_err(|e| e.to_string())?;
-    let mut sp = StringPath::new(settings)?;
+    let sp = StringPath::new(settings)?;
     //sp.fill_unique_pixels();
-    let window = create_window("Image", Default::default()).unwrap();
-    while sp.step() 
+    continue_path(sp, preview)
+}
+
+//Continue generating a path from its latest checkpoint
+pub fn resume_path(settings: StringSettings, preview: bool) -> Result<StringPath, String>
+{
+    let sp = StringPath::resume(settings)?;
+    println!("Resuming from step {}", sp.cur_step);
+    continue_path(sp, preview)
+}
+
+fn continue_path(mut sp: StringPath, preview: bool) -> Result<StringPath, String>
+{
+    let checkpoint_interval = sp.settings().checkpoint_interval;
+    let window = if preview {Some(create_window("Image", Default::default()).map_err(|e| e.to_string())?)} else {None};
+    while sp.step().is_ok()
     {
-        if sp.cur_step % 100 == 0
+        if let Some(window) = window.as_ref().filter(|_| sp.cur_step.is_multiple_of(100))
         {
-            let binding =  DynamicImage::ImageRgb32F(sp.strings_drawn.as_rgb_image_buffer()).into_rgb8();
+            let mut binding =  DynamicImage::ImageRgb32F(sp.strings_drawn.as_rgb_image_buffer()).into_rgb8();
+            draw_pins(&mut binding, &sp.pin_positions);
             let window_image  = ImageView::new(ImageInfo::rgb8(sp.strings_drawn.width(), sp.strings_drawn.height()), binding.as_bytes());
-            window.set_image("input_image", window_image).unwrap();
+            window.set_image("input_image", window_image).map_err(|e| e.to_string())?;
         }
-        if (sp.cur_step+1) % 500 == 0
+        if sp.cur_step.is_multiple_of(500)
         {
-            sp.save_visual().unwrap();
+            sp.save_visual()?;
+        }
+        if checkpoint_interval.is_some_and(|interval| sp.cur_step.is_multiple_of(interval))
+        {
+            sp.save_checkpoint()?;
         }
         println!("{:?}:\t{:?} \tScores: {:?}%",sp.cur_step, sp.cur_idxs, sp.cur_scores);
     }
-    return Ok(sp);
+    if let Some(reason) = sp.finished()
+    {
+        println!("Stopped after {} strings because {reason}", sp.path.len());
+    }
+    if let Some(summary) = sp.refine()
+    {
+        if summary.error_after < summary.error_before
+        {
+            println!("Refinement moved {} pins and skipped {} strings, lowering the mean error from {:.4} to {:.4}",
+                summary.rerouted, summary.removed, summary.error_before, summary.error_after);
+        }
+        else
+        {
+            println!("Refinement found no improvement, keeping the greedy path");
+        }
+    }
+    Ok(sp)
 }
 
+
+//Mark each pin in red on the preview image
+fn draw_pins(image: &mut RgbImage, pins: &[(f32, f32)])
+{
+    let radius = (image.width().max(image.height()) / 512).max(1) as i64;
+    for &(x, y) in pins
+    {
+        for dx in -radius..=radius
+        {
+            for dy in -radius..=radius
+            {
+                let (px, py) = (x.round() as i64 + dx, y.round() as i64 + dy);
+                if px >= 0 && py >= 0 && px < image.width() as i64 && py < image.height() as i64
+                {
+                    image.put_pixel(px as u32, py as u32, Rgb([255, 0, 0]));
+                }
+            }
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,331 @@
+use super::string_setting::{StringSettings, PinLayoutKind};
+
+use std::f32::consts::PI;
+use std::path::Path;
+
+//Arrangement of the pins around the image. Pins are always listed in order along the frame.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PinLayout
+{
+    //Circle with a radius relative to the smaller image dimension
+    Circle {pin_count : usize, radius : f32},
+    //Circle stretched to the image's aspect ratio
+    Ellipse {pin_count : usize, radius : f32},
+    //Perimeter of a rectangle relative to the image dimensions, pins evenly spaced or every `spacing` pixels
+    Rectangle {pin_count : usize, scale : f32, spacing : Option<f32>},
+    //Perimeter of a regular polygon, with a circumradius relative to the smaller image dimension
+    Polygon {pin_count : usize, sides : usize, radius : f32},
+    //Explicit pin positions in pixels
+    Custom(Vec<(f32, f32)>)
+}
+
+impl PinLayout
+{
+    pub fn from_settings(settings: &StringSettings) -> Result<PinLayout, String>
+    {
+        let pin_count = settings.pin_count;
+        let radius = settings.pin_radius;
+        let layout = match settings.pin_layout
+        {
+            PinLayoutKind::Circle => PinLayout::Circle {pin_count, radius},
+            PinLayoutKind::Ellipse => PinLayout::Ellipse {pin_count, radius},
+            PinLayoutKind::Rectangle => PinLayout::Rectangle {pin_count, scale: radius, spacing: settings.pin_spacing},
+            PinLayoutKind::Polygon => PinLayout::Polygon {pin_count, sides: settings.polygon_sides, radius},
+            PinLayoutKind::Custom =>
+            {
+                let pin_file = settings.pin_file.as_ref().ok_or("The custom pin_layout requires a pin_file.")?;
+                PinLayout::Custom(load_pin_file(pin_file)?)
+            }
+        };
+        Ok(layout)
+    }
+
+    //Pin positions in pixels for an image of the given dimensions
+    pub fn positions(&self, dimensions: (u32, u32)) -> Result<Vec<(f32, f32)>, String>
+    {
+        let center = ((dimensions.0/2) as f32, (dimensions.1/2) as f32);
+        let pins = match self
+        {
+            PinLayout::Circle {pin_count, radius} =>
+            {
+                check_scale(*radius)?;
+                let r = center.0.min(center.1) * radius;
+                ring(*pin_count, center, (r, r))
+            },
+            PinLayout::Ellipse {pin_count, radius} =>
+            {
+                check_scale(*radius)?;
+                ring(*pin_count, center, (center.0 * radius, center.1 * radius))
+            },
+            PinLayout::Rectangle {pin_count, scale, spacing} =>
+            {
+                check_scale(*scale)?;
+                let (half_w, half_h) = (center.0 * scale, center.1 * scale);
+                let corners = vec![
+                    (center.0 - half_w, center.1 - half_h),
+                    (center.0 + half_w, center.1 - half_h),
+                    (center.0 + half_w, center.1 + half_h),
+                    (center.0 - half_w, center.1 + half_h)
+                ];
+                along_perimeter(&corners, *pin_count, *spacing)
+            },
+            PinLayout::Polygon {pin_count, sides, radius} =>
+            {
+                check_scale(*radius)?;
+                if *sides < 3 {return Err(format!("A polygon needs at least 3 sides, got {sides}."))};
+                let r = center.0.min(center.1) * radius;
+                let corners = ring(*sides, center, (r, r));
+                along_perimeter(&corners, *pin_count, None)
+            },
+            PinLayout::Custom(pins) => pins.clone()
+        };
+        if pins.len() < 2
+        {
+            return Err(format!("The pin layout only has {} pins, at least 2 are needed.", pins.len()));
+        }
+        //Lines are scored with the pixels on either side of them, so pins must stay one pixel inside the image
+        let max = (dimensions.0 as f32 - 2., dimensions.1 as f32 - 2.);
+        if let Some((idx, pin)) = pins.iter().enumerate().find(|(_, p)| p.0 < 1. || p.1 < 1. || p.0 > max.0 || p.1 > max.1)
+        {
+            return Err(format!("Pin {idx} at {pin:?} is outside of the {}x{} image.", dimensions.0, dimensions.1));
+        }
+        Ok(pins)
+    }
+}
+
+//Pairs of pins which lie on the same straight side of the frame, with other pins in between.
+//  The string between them would just run along the frame.
+pub fn frame_side_pairs(pins: &[(f32, f32)]) -> Vec<(usize, usize)>
+{
+    //Of the spacing between the pins the side runs through. Pins on a ring of up to 600 pins stay further off the side.
+    const TOLERANCE : f32 = 0.01;
+    let count = pins.len();
+    let mut pairs = Vec::new();
+    for from in 0..count
+    {
+        let a = pins[from];
+        let b = pins[(from + 1) % count];
+        let dir = (b.0 - a.0, b.1 - a.1);
+        let len = (dir.0*dir.0 + dir.1*dir.1).sqrt();
+        if len == 0. {continue};
+        for offset in 2..count-1
+        {
+            let to = (from + offset) % count;
+            let p = pins[to];
+            let distance = ((p.0 - a.0) * dir.1 - (p.1 - a.1) * dir.0).abs() / len;
+            if distance > TOLERANCE * len {break};
+            pairs.push((from.min(to), from.max(to)));
+        }
+    }
+    pairs
+}
+
+/*Pairs of pins fewer than min_gap pins apart around the outline, or seeing each other at less than min_angle_deg from
+    the center of the pins. Their strings would hug the rim.
+ */
+pub fn close_pairs(pins: &[(f32, f32)], min_gap: usize, min_angle_deg: f32) -> Vec<(usize, usize)>
+{
+    let count = pins.len();
+    let center = pins.iter().fold((0., 0.), |sum, p| (sum.0 + p.0 / count as f32, sum.1 + p.1 / count as f32));
+    let angles: Vec<f32> = pins.iter().map(|p| (p.1 - center.1).atan2(p.0 - center.0)).collect();
+    let mut pairs = Vec::new();
+    for a in 0..count
+    {
+        for b in a+1..count
+        {
+            let gap = (b - a).min(count - (b - a));
+            let angle = (angles[b] - angles[a]).abs();
+            let angle = angle.min(2. * PI - angle).to_degrees();
+            if gap < min_gap || angle < min_angle_deg
+            {
+                pairs.push((a, b));
+            }
+        }
+    }
+    pairs
+}
+
+fn check_scale(scale: f32) -> Result<(), String>
+{
+    if scale > 0. && scale < 1. {Ok(())} else {Err(format!("pin_radius must be in (0,1), got {scale}."))}
+}
+
+fn ring(pin_count: usize, center: (f32, f32), radius: (f32, f32)) -> Vec<(f32, f32)>
+{
+    (0..pin_count).map(|i|
+    {
+        let angle = PI * 2. * (i as f32) / pin_count as f32;
+        (center.0 + angle.cos() * radius.0, center.1 + angle.sin() * radius.1)
+    }).collect()
+}
+
+//Start, end and length of a side of an outline
+type Side = ((f32, f32), (f32, f32), f32);
+
+//Pins along the closed outline through the given corners, starting at the first corner
+fn along_perimeter(corners: &[(f32, f32)], pin_count: usize, spacing: Option<f32>) -> Vec<(f32, f32)>
+{
+    let sides: Vec<Side> = corners.iter().enumerate()
+        .map(|(i, &a)|
+        {
+            let b = corners[(i + 1) % corners.len()];
+            (a, b, ((b.0 - a.0).powi(2) + (b.1 - a.1).powi(2)).sqrt())
+        })
+        .collect();
+    let perimeter: f32 = sides.iter().map(|s| s.2).sum();
+    let (spacing, count) = match spacing
+    {
+        Some(spacing) => (spacing, (perimeter / spacing).floor() as usize),
+        None => (perimeter / pin_count as f32, pin_count)
+    };
+    let mut pins = Vec::with_capacity(count);
+    let mut side_idx = 0;
+    let mut side_start = 0.;
+    for i in 0..count
+    {
+        let distance = i as f32 * spacing;
+        while side_idx + 1 < sides.len() && distance >= side_start + sides[side_idx].2
+        {
+            side_start += sides[side_idx].2;
+            side_idx += 1;
+        }
+        let (a, b, len) = sides[side_idx];
+        let t = (distance - side_start) / len;
+        pins.push((a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t));
+    }
+    pins
+}
+
+//Read pin positions in pixels from a CSV file with x and y columns, or from the circles of an SVG file
+fn load_pin_file(path: &str) -> Result<Vec<(f32, f32)>, String>
+{
+    let is_svg = Path::new(path).extension().is_some_and(|e| e.eq_ignore_ascii_case("svg"));
+    if is_svg
+    {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        return parse_svg_pins(&text).map_err(|e| format!("{path}: {e}"));
+    }
+    let mut reader = csv::Reader::from_path(path).map_err(|e| format!("{path}: {e}"))?;
+    let headers = reader.headers().map_err(|e| format!("{path}: {e}"))?.clone();
+    let column = |name: &str| headers.iter().position(|h| h.trim() == name)
+        .ok_or(format!("{path}: missing column \"{name}\""));
+    let (x_col, y_col) = (column("x")?, column("y")?);
+    let mut pins = Vec::new();
+    for (row, record) in reader.records().enumerate()
+    {
+        let record = record.map_err(|e| format!("{path}: {e}"))?;
+        let parse = |col: usize| record.get(col).and_then(|v| v.trim().parse::<f32>().ok())
+            .ok_or(format!("{path}: row {} does not have a number in column {col}", row + 1));
+        pins.push((parse(x_col)?, parse(y_col)?));
+    }
+    Ok(pins)
+}
+
+//Centers of every <circle> element, scaled from the viewBox to pixels if the SVG has both a viewBox and a size
+fn parse_svg_pins(text: &str) -> Result<Vec<(f32, f32)>, String>
+{
+    let mut scale = ((0., 0.), (1., 1.));
+    if let Some(svg_tag) = text.split("<svg").nth(1).and_then(|t| t.split('>').next())
+    {
+        let size = svg_number(svg_tag, "width").zip(svg_number(svg_tag, "height"));
+        let view_box: Option<Vec<f32>> = svg_attribute(svg_tag, "viewBox")
+            .map(|v| v.split(|c: char| c == ',' || c.is_whitespace()).filter(|s| !s.is_empty()).filter_map(|s| s.parse().ok()).collect());
+        if let (Some((width, height)), Some(view_box)) = (size, view_box.filter(|v| v.len() == 4))
+        {
+            scale = ((view_box[0], view_box[1]), (width / view_box[2], height / view_box[3]));
+        }
+    }
+    let mut pins = Vec::new();
+    for tag in text.split("<circle").skip(1).filter_map(|t| t.split('>').next())
+    {
+        match (svg_number(tag, "cx"), svg_number(tag, "cy"))
+        {
+            (Some(cx), Some(cy)) => pins.push(((cx - scale.0.0) * scale.1.0, (cy - scale.0.1) * scale.1.1)),
+            _ => return Err(format!("circle without numeric cx and cy: <circle{tag}>"))
+        }
+    }
+    Ok(pins)
+}
+
+fn svg_attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str>
+{
+    for quote in ['"', '\'']
+    {
+        let pattern = format!("{name}={quote}");
+        let found = tag.match_indices(&pattern)
+            .find(|(idx, _)| *idx == 0 || tag[..*idx].ends_with(char::is_whitespace));
+        if let Some((idx, _)) = found
+        {
+            let value = &tag[idx + pattern.len()..];
+            return value.split(quote).next();
+        }
+    }
+    None
+}
+
+fn svg_number(tag: &str, name: &str) -> Option<f32>
+{
+    svg_attribute(tag, name).and_then(|v| v.trim().trim_end_matches("px").parse().ok())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn layouts_stay_inside_image()
+    {
+        let layouts = [
+            PinLayout::Circle {pin_count: 100, radius: 0.95},
+            PinLayout::Ellipse {pin_count: 100, radius: 0.95},
+            PinLayout::Rectangle {pin_count: 100, scale: 0.95, spacing: None},
+            PinLayout::Rectangle {pin_count: 0, scale: 0.95, spacing: Some(10.)},
+            PinLayout::Polygon {pin_count: 100, sides: 6, radius: 0.95}
+        ];
+        for layout in layouts
+        {
+            let pins = layout.positions((300, 200)).unwrap();
+            assert!(pins.len() > 50, "{layout:?}");
+        }
+        let circle = PinLayout::Circle {pin_count: 4, radius: 0.5}.positions((300, 200)).unwrap();
+        assert!((circle[0].0 - 200.).abs() < 1e-3 && (circle[1].1 - 150.).abs() < 1e-3);
+    }
+
+    #[test]
+    fn rectangle_sides_are_banned()
+    {
+        let pins = PinLayout::Rectangle {pin_count: 8, scale: 0.5, spacing: None}.positions((100, 100)).unwrap();
+        //Corners and side midpoints: each side holds corner, midpoint, corner
+        let pairs = frame_side_pairs(&pins);
+        assert_eq!(pairs.len(), 4);
+        assert!(pairs.contains(&(0, 2)) && pairs.contains(&(0, 6)));
+        let circle = PinLayout::Circle {pin_count: 8, radius: 0.5}.positions((100, 100)).unwrap();
+        assert!(frame_side_pairs(&circle).is_empty());
+        //Pins on a large ring are a fraction of a pixel off the line through their neighbours, but no side holds three of them
+        let circle = PinLayout::Circle {pin_count: 250, radius: 0.95}.positions((1000, 1000)).unwrap();
+        assert!(frame_side_pairs(&circle).is_empty());
+        let hexagon = PinLayout::Polygon {pin_count: 600, sides: 6, radius: 0.95}.positions((1000, 1000)).unwrap();
+        assert!(frame_side_pairs(&hexagon).contains(&(1, 3)));
+    }
+
+    #[test]
+    fn svg_circles_are_scaled()
+    {
+        let svg = r#"<svg width="200" height="100" viewBox="0 0 20 10"><circle cx="5" cy="5" r="1"/><circle r="1" cx="15" cy="2.5"/></svg>"#;
+        assert_eq!(parse_svg_pins(svg).unwrap(), vec![(50., 50.), (150., 25.)]);
+    }
+
+    #[test]
+    fn close_pins_are_banned()
+    {
+        let pins = PinLayout::Circle {pin_count: 12, radius: 0.9}.positions((100, 100)).unwrap();
+        assert!(close_pairs(&pins, 1, 0.).is_empty());
+        //Neighbours, including across the first and last pin
+        let neighbours = close_pairs(&pins, 2, 0.);
+        assert_eq!(neighbours.len(), 12);
+        assert!(neighbours.contains(&(0, 11)));
+        //Pins are 30 degrees apart
+        assert_eq!(close_pairs(&pins, 1, 45.), close_pairs(&pins, 2, 0.));
+    }
+}
@@ -0,0 +1,435 @@
+use crate::image_module::lab::{LabImageBuffer, ColorMetric};
+use crate::tri_vec::TriVec;
+use super::string_path::PathStep;
+use super::board::{Board, ChordSides, WrapDirection};
+
+use line_drawing::XiaolinWu;
+use palette::{Lab, Mix};
+use rand::Rng;
+
+/*The drawn strings at a reduced resolution, where each cell averages a square of pixels like the eye does from a distance.
+    Strings are summed per color, so that they can be taken away again, and the colors are stacked in the given order.
+ */
+pub struct CellImage
+{
+    downscale : u32,
+    columns : u32,
+    rows : u32,
+    target : Vec<Lab>, //Mean input color of each cell
+    darkness : Vec<Vec<f32>>, //Area of each cell covered by each color's strings, overlaps counted twice
+    order : Vec<usize>, //Colors from the bottom to the top
+    colors : Vec<Lab>,
+    background : Lab,
+    strength : f32,
+    metric : ColorMetric
+}
+
+impl CellImage
+{
+    pub fn new(target: &LabImageBuffer, downscale: u32, order: Vec<usize>, colors: &[Lab], background: &Lab, strength: f32, metric: ColorMetric) -> CellImage
+    {
+        let (width, height) = target.dimensions();
+        let (columns, rows) = (width.div_ceil(downscale), height.div_ceil(downscale));
+        let mut sums = vec![([0_f32; 3], 0_u32); (columns * rows) as usize];
+        for (idx, pixel) in target.as_raw().chunks(3).enumerate()
+        {
+            let (x, y) = (idx as u32 % width, idx as u32 / width);
+            let (sum, count) = &mut sums[((y / downscale) * columns + x / downscale) as usize];
+            sum.iter_mut().zip(pixel).for_each(|(s, p)| *s += p);
+            *count += 1;
+        }
+        CellImage
+        {
+            downscale,
+            columns,
+            rows,
+            target: sums.iter().map(|(sum, count)| Lab::new(sum[0] / *count as f32, sum[1] / *count as f32, sum[2] / *count as f32)).collect(),
+            darkness: vec![vec![0.; (columns * rows) as usize]; colors.len()],
+            order,
+            colors: colors.to_vec(),
+            background: *background,
+            strength,
+            metric
+        }
+    }
+
+    //Cells covered by the string between the given ends, with the area of each it covers
+    fn string_cells(&self, (from, to): ((f32, f32), (f32, f32))) -> Vec<(usize, f32)>
+    {
+        let cell_area = (self.downscale * self.downscale) as f32;
+        XiaolinWu::<f32, i32>::new(from, to)
+            .filter(|((x, y), _)| *x >= 0 && *y >= 0 && (*x as u32) < self.columns * self.downscale && (*y as u32) < self.rows * self.downscale)
+            .map(|((x, y), weight)| (((y as u32 / self.downscale) * self.columns + x as u32 / self.downscale) as usize, weight * self.strength / cell_area))
+            .collect()
+    }
+
+    //Add a string of the given color, or take it away again with a sign of -1
+    fn draw(&mut self, color_idx: usize, ends: ((f32, f32), (f32, f32)), sign: f32)
+    {
+        for (cell, area) in self.string_cells(ends)
+        {
+            self.darkness[color_idx][cell] += sign * area;
+        }
+    }
+
+    //Difference between the composited strings and the input in the given cell
+    fn error(&self, cell: usize) -> f32
+    {
+        let drawn = self.order.iter().fold(self.background, |below, &color_idx|
+        {
+            //Strings crossing at random cover less together than their summed area
+            let coverage = 1. - (-self.darkness[color_idx][cell].max(0.)).exp();
+            below.mix(&self.colors[color_idx], coverage)
+        });
+        self.metric.difference(&drawn, &self.target[cell])
+    }
+
+    pub fn mean_error(&self) -> f32
+    {
+        let cell_count = self.target.len();
+        (0..cell_count).map(|cell| self.error(cell)).sum::<f32>() / cell_count as f32
+    }
+}
+
+//What every string of the refined path has to keep to, like the strings the greedy search drew
+pub struct RefineLimits
+{
+    pub allowed : TriVec<bool>, //Pairs of pins which may be connected at all
+    pub max_chord_uses : Option<usize>,
+    pub max_chord_reuse : Option<usize>,
+    pub max_pin_wraps : Option<usize>,
+    pub thread_budget_mm : Option<f32>,
+    pub board : Option<Board>
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RefineSummary
+{
+    pub error_before : f32, //Mean cell error of the greedy path
+    pub error_after : f32,
+    pub removed : usize, //Strings taken out of the path
+    pub rerouted : usize //Pins replaced by another pin
+}
+
+//A string of a color's route, between two pins and wound the given ways around each
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Strand
+{
+    from : usize,
+    to : usize,
+    from_wrap : WrapDirection,
+    to_wrap : WrapDirection
+}
+
+//A change to one color's route, replacing the strings to and from one of its pins
+struct Change
+{
+    color_idx : usize,
+    route_pos : usize, //Position in the color's route of the step reaching the pin
+    new_pin : Option<(usize, WrapDirection)>, //The pin is skipped if there is none
+    removed : Vec<Strand>,
+    added : Vec<Strand>
+}
+
+/*Local search over a finished path, moving or skipping single pins of a color's route while the error against the input drops.
+    Worse changes are accepted with a probability falling with the temperature, which cools down to 0 over the iterations.
+ */
+pub struct Refiner<'a>
+{
+    pins : &'a [(f32, f32)],
+    sides : ChordSides,
+    image : CellImage,
+    limits : RefineLimits,
+    steps : Vec<Option<PathStep>>, //The path in its original order, skipped steps are None
+    routes : Vec<Vec<usize>>, //Indices into steps of each color's remaining steps
+    chord_uses : TriVec<Vec<usize>>,
+    pin_wraps : Vec<usize>,
+    thread_mm : f32
+}
+
+impl<'a> Refiner<'a>
+{
+    //Strings are drawn where they touch the nails, which the given sides are sized by
+    pub fn new(path: &[PathStep], pins: &'a [(f32, f32)], sides: ChordSides, image: CellImage, limits: RefineLimits) -> Refiner<'a>
+    {
+        let color_count = image.colors.len();
+        let mut refiner = Refiner
+        {
+            pins,
+            sides,
+            image,
+            limits,
+            steps: path.iter().map(|s| Some(*s)).collect(),
+            routes: vec![Vec::new(); color_count],
+            chord_uses: TriVec::new(pins.len(), &vec![0; color_count]),
+            pin_wraps: vec![0; pins.len()],
+            thread_mm: 0.
+        };
+        for (idx, step) in path.iter().enumerate()
+        {
+            refiner.routes[step.color_idx].push(idx);
+            let strand = refiner.strand(step.color_idx, refiner.routes[step.color_idx].len() - 1);
+            refiner.chord_uses.at(step.from_idx, step.to_idx)[step.color_idx] += 1;
+            refiner.pin_wraps[step.to_idx] += 1;
+            refiner.thread_mm += refiner.length_mm(&strand);
+            refiner.image.draw(step.color_idx, refiner.ends(&strand), 1.);
+        }
+        refiner
+    }
+
+    pub fn run(&mut self, iterations: usize, temperature: f32, rng: &mut impl Rng) -> RefineSummary
+    {
+        let mut summary = RefineSummary {error_before: self.image.mean_error(), error_after: 0., removed: 0, rerouted: 0};
+        for iteration in 0..iterations
+        {
+            //A new pin is wound around whichever way lowers the error most
+            let mut best: Option<(Change, f32)> = None;
+            for change in self.propose(rng).into_iter().filter(|change| self.fits(change)).collect::<Vec<_>>()
+            {
+                let delta = self.apply(&change, 1.);
+                self.apply(&change, -1.);
+                if best.as_ref().is_none_or(|(_, best_delta)| delta < *best_delta)
+                {
+                    best = Some((change, delta));
+                }
+            }
+            let Some((change, delta)) = best else {continue};
+            let cooled = temperature * (1. - iteration as f32 / iterations as f32);
+            if delta < 0. || (cooled > 0. && rng.gen::<f32>() < (-delta / cooled).exp())
+            {
+                self.apply(&change, 1.);
+                self.commit(&change);
+                if change.new_pin.is_some() {summary.rerouted += 1} else {summary.removed += 1};
+            }
+        }
+        summary.error_after = self.image.mean_error();
+        summary
+    }
+
+    //The refined path, keeping the order of the strings which are left. Changed strings have a score of 0.
+    pub fn path(&self) -> Vec<PathStep>
+    {
+        self.steps.iter().flatten().copied().collect()
+    }
+
+    /*Move the pin reached by a random step of a random color to a random other pin, or skip it. A moved pin is proposed
+        wound around both ways if the nails have a size, otherwise the way the step was wound.
+     */
+    fn propose(&self, rng: &mut impl Rng) -> Vec<Change>
+    {
+        let color_idx = rng.gen_range(0..self.routes.len());
+        let route = &self.routes[color_idx];
+        if route.is_empty() {return Vec::new()};
+        let route_pos = rng.gen_range(0..route.len());
+        let Some(step) = self.steps[route[route_pos]] else {return Vec::new()};
+        let next = route.get(route_pos + 1).map(|_| self.strand(color_idx, route_pos + 1));
+        let mut removed = vec![self.strand(color_idx, route_pos)];
+        removed.extend(next);
+        let from_wrap = removed[0].from_wrap;
+        //The first string of a route leaves its pin the way it reaches the next one
+        let leaving = |wrap: WrapDirection| if route_pos == 0 {wrap} else {from_wrap};
+        let strand = |from, to, from_wrap, to_wrap| Strand {from, to, from_wrap, to_wrap};
+        if rng.gen_bool(0.5)
+        {
+            let pin = rng.gen_range(0..self.pins.len());
+            if pin == step.to_idx || pin == step.from_idx || next.is_some_and(|n| n.to == pin) {return Vec::new()};
+            let wraps = if self.sides.count() > 1 {vec![WrapDirection::Clockwise, WrapDirection::CounterClockwise]} else {vec![step.wrap]};
+            wraps.into_iter().map(|wrap|
+            {
+                let mut added = vec![strand(step.from_idx, pin, leaving(wrap), wrap)];
+                added.extend(next.map(|n| strand(pin, n.to, wrap, n.to_wrap)));
+                Change {color_idx, route_pos, new_pin: Some((pin, wrap)), removed: removed.clone(), added}
+            }).collect()
+        }
+        else
+        {
+            //Straight on to the pin after the skipped one
+            let added: Vec<Strand> = next.filter(|n| n.to != step.from_idx).map(|n| strand(step.from_idx, n.to, leaving(n.to_wrap), n.to_wrap)).into_iter().collect();
+            if next.is_some() && added.is_empty() {return Vec::new()};
+            vec![Change {color_idx, route_pos, new_pin: None, removed, added}]
+        }
+    }
+
+    //The string of the step at the given position of the color's route, leaving its pin the way the step before reached it
+    fn strand(&self, color_idx: usize, route_pos: usize) -> Strand
+    {
+        let step_at = |pos: usize| self.steps[self.routes[color_idx][pos]].unwrap();
+        let step = step_at(route_pos);
+        let from_wrap = if route_pos == 0 {step.wrap} else {step_at(route_pos - 1).wrap};
+        Strand {from: step.from_idx, to: step.to_idx, from_wrap, to_wrap: step.wrap}
+    }
+
+    fn ends(&self, strand: &Strand) -> ((f32, f32), (f32, f32))
+    {
+        self.sides.string_ends(self.pins[strand.from], self.pins[strand.to], strand.from_wrap, strand.to_wrap)
+    }
+
+    //Whether the route after the change keeps to the limits
+    fn fits(&mut self, change: &Change) -> bool
+    {
+        if change.added.iter().any(|s| !*self.limits.allowed.get(s.from, s.to))
+        {
+            return false;
+        }
+        self.count(change, 1);
+        let uses_fit = change.added.iter().all(|s|
+        {
+            let uses = self.chord_uses.get(s.from, s.to);
+            self.limits.max_chord_uses.is_none_or(|max| uses.iter().sum::<usize>() <= max)
+                && self.limits.max_chord_reuse.is_none_or(|max| uses[change.color_idx] <= max + 1)
+        });
+        let wraps_fit = change.new_pin.is_none_or(|(pin, _)| self.limits.max_pin_wraps.is_none_or(|max| self.pin_wraps[pin] <= max));
+        let length_fits = self.limits.thread_budget_mm.is_none_or(|budget| self.thread_mm <= budget);
+        self.count(change, -1);
+        uses_fit && wraps_fit && length_fits
+    }
+
+    //Count the uses of the changed strings and pins, or uncount them with a sign of -1
+    fn count(&mut self, change: &Change, sign: isize)
+    {
+        //Uses are only ever taken away after being counted, anything else is a bookkeeping error
+        let add = |count: &mut usize, delta: isize| *count = count.checked_add_signed(delta).expect("use counts of the refined path went negative");
+        let color_idx = change.color_idx;
+        for (strand, direction) in change.removed.iter().map(|s| (s, -sign)).chain(change.added.iter().map(|s| (s, sign)))
+        {
+            add(&mut self.chord_uses.at(strand.from, strand.to)[color_idx], direction);
+            self.thread_mm += direction as f32 * self.length_mm(strand);
+        }
+        let old_pin = self.steps[self.routes[color_idx][change.route_pos]].unwrap().to_idx;
+        add(&mut self.pin_wraps[old_pin], -sign);
+        if let Some((pin, _)) = change.new_pin
+        {
+            add(&mut self.pin_wraps[pin], sign);
+        }
+    }
+
+    //Draw the change's strings instead of the ones it replaces, or undo that with a sign of -1, returning the change in error
+    fn apply(&mut self, change: &Change, sign: f32) -> f32
+    {
+        let mut cells: Vec<usize> = change.removed.iter().chain(change.added.iter())
+            .flat_map(|s| self.image.string_cells(self.ends(s)))
+            .map(|(cell, _)| cell)
+            .collect();
+        cells.sort_unstable();
+        cells.dedup();
+        let error = |image: &CellImage| cells.iter().map(|&cell| image.error(cell)).sum::<f32>();
+        let before = error(&self.image);
+        for strand in change.removed.iter()
+        {
+            self.image.draw(change.color_idx, self.ends(strand), -sign);
+        }
+        for strand in change.added.iter()
+        {
+            self.image.draw(change.color_idx, self.ends(strand), sign);
+        }
+        error(&self.image) - before
+    }
+
+    //Rewrite the steps of an accepted change
+    fn commit(&mut self, change: &Change)
+    {
+        self.count(change, 1);
+        let route = &mut self.routes[change.color_idx];
+        let step_idx = route[change.route_pos];
+        let next_idx = route.get(change.route_pos + 1).copied();
+        match change.new_pin
+        {
+            Some((pin, wrap)) =>
+            {
+                let step = self.steps[step_idx].as_mut().unwrap();
+                step.to_idx = pin;
+                step.wrap = wrap;
+                step.score = 0.;
+                if let Some(next) = next_idx.and_then(|idx| self.steps[idx].as_mut())
+                {
+                    next.from_idx = pin;
+                    next.score = 0.;
+                }
+            },
+            None =>
+            {
+                let from = self.steps[step_idx].unwrap().from_idx;
+                self.steps[step_idx] = None;
+                route.remove(change.route_pos);
+                if let Some(next) = next_idx.and_then(|idx| self.steps[idx].as_mut())
+                {
+                    next.from_idx = from;
+                    next.score = 0.;
+                }
+            }
+        }
+    }
+
+    fn length_mm(&self, strand: &Strand) -> f32
+    {
+        let (from, to) = self.ends(strand);
+        self.limits.board.map_or(0., |board| board.string_length_mm(from, to))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::image_module::lab::LabBuf;
+    use crate::string_path::board::WrapDirection;
+    use rand::SeedableRng;
+
+    #[test]
+    fn stray_strings_are_removed()
+    {
+        //A dark horizontal band, with one string through it and one across the white part
+        let white = Lab::new(100., 0., 0.);
+        let black = Lab::new(0., 0., 0.);
+        let mut target = LabImageBuffer::from_lab(32, 32, &white);
+        for x in 0..32 {target.put_pixel(x, 8, &black)};
+        let pins = [(0., 8.), (31., 8.), (31., 24.)];
+        let step = |from_idx, to_idx| PathStep {from_idx, to_idx, color_idx: 0, score: 1., wrap: WrapDirection::Clockwise};
+        let path = [step(0, 1), step(1, 2)];
+        let image = CellImage::new(&target, 2, vec![0], &[black], &white, 1., ColorMetric::Euclidean);
+        let limits = RefineLimits
+        {
+            allowed: TriVec::new(3, &true),
+            max_chord_uses: Some(1),
+            max_chord_reuse: None,
+            max_pin_wraps: None,
+            thread_budget_mm: None,
+            board: None
+        };
+        let mut refiner = Refiner::new(&path, &pins, ChordSides::new(0.), image, limits);
+        let summary = refiner.run(50, 0., &mut rand_chacha::ChaCha8Rng::seed_from_u64(1));
+        assert!(summary.error_after < summary.error_before, "{summary:?}");
+        assert_eq!(refiner.path(), vec![step(0, 1)]);
+    }
+
+    #[test]
+    fn refined_path_draws_what_was_scored()
+    {
+        //Strings wound either way round nails of a 1.5 pixel radius on a ring around a dark disc
+        let white = Lab::new(100., 0., 0.);
+        let black = Lab::new(0., 0., 0.);
+        let mut target = LabImageBuffer::from_lab(48, 48, &white);
+        for y in 0..48 {for x in 0..48 {if (x as f32 - 24.).powi(2) + (y as f32 - 24.).powi(2) < 300. {target.put_pixel(x, y, &black)}}};
+        let pins: Vec<(f32, f32)> = (0..16).map(|i| i as f32 * std::f32::consts::TAU / 16.).map(|a| (24. + 22. * a.cos(), 24. + 22. * a.sin())).collect();
+        let path: Vec<PathStep> = (0..30).map(|i| PathStep {from_idx: i * 7 % 16, to_idx: (i + 1) * 7 % 16, color_idx: 0, score: 1., wrap: WrapDirection::Clockwise}).collect();
+        let sides = ChordSides::new(1.5);
+        let image = || CellImage::new(&target, 2, vec![0], &[black], &white, 0.5, ColorMetric::Euclidean);
+        let limits = || RefineLimits
+        {
+            allowed: TriVec::new(16, &true),
+            max_chord_uses: None,
+            max_chord_reuse: None,
+            max_pin_wraps: None,
+            thread_budget_mm: None,
+            board: Some(Board {mm_per_pixel: 1., nail_diameter_mm: 3., thread_thickness_mm: 0.5, thread_opacity: 1.})
+        };
+        let mut refiner = Refiner::new(&path, &pins, sides, image(), limits());
+        let summary = refiner.run(2000, 0., &mut rand_chacha::ChaCha8Rng::seed_from_u64(2));
+        let refined = refiner.path();
+        assert!(summary.rerouted > 0 && refined.iter().any(|s| s.wrap == WrapDirection::CounterClockwise), "{summary:?}");
+        //Drawing the refined path again gives the error and thread length the search ended with
+        let redrawn = Refiner::new(&refined, &pins, sides, image(), limits());
+        assert!((redrawn.image.mean_error() - summary.error_after).abs() < 1e-3, "{} {summary:?}", redrawn.image.mean_error());
+        assert!((redrawn.thread_mm - refiner.thread_mm).abs() < 1e-2, "{} {}", redrawn.thread_mm, refiner.thread_mm);
+    }
+}
@@ -0,0 +1,170 @@
+use super::string_path::{PathStep, StepError};
+use super::board::{Board, WrapDirection, tangent_chord};
+use super::thread_catalog::Thread;
+use crate::image_module::evaluation::Evaluation;
+
+use std::fmt::Write;
+use std::fs::File;
+use serde::Serialize;
+
+//How much thread a path uses, to order thread per color
+#[derive(Serialize)]
+pub struct PathReport
+{
+    pub step_count : usize,
+    pub colors : Vec<ColorReport>,
+    pub total_length_px : f32,
+    pub total_length_mm : Option<f32>, //Only known if the board diameter is set
+    pub total_cost : Option<f32>, //Only known if every thread has a price
+    pub pin_wraps : Vec<usize>, //Times the string is wrapped around each pin, over all colors
+    pub quality : Option<Evaluation>,
+    pub stop_reason : Option<StepError> //Not set while the path is still being generated
+}
+
+#[derive(Serialize)]
+pub struct ColorReport
+{
+    pub index : usize,
+    pub name : String,
+    pub thread : Option<Thread>,
+    pub steps : usize,
+    pub length_px : f32, //Sum of the straight strings between the points where they touch the nails
+    pub length_mm : Option<f32>, //Chords plus half a turn around the nail at every wrap
+    pub wraps : usize,
+    pub cost : Option<f32>
+}
+
+impl PathReport
+{
+    pub fn new(path: &[PathStep], pins: &[(f32, f32)], color_names: &[String], threads: &[Thread], board: Option<&Board>) -> PathReport
+    {
+        let mut colors: Vec<ColorReport> = color_names.iter().enumerate()
+            .map(|(index, name)| ColorReport
+            {
+                index,
+                name: name.clone(),
+                thread: threads.get(index).cloned(),
+                steps: 0,
+                length_px: 0.,
+                length_mm: None,
+                wraps: 0,
+                cost: None
+            })
+            .collect();
+        let mut pin_wraps = vec![0; pins.len()];
+        let nail_radius = board.map_or(0., |board| board.nail_radius());
+        let mut wraps: Vec<Option<WrapDirection>> = vec![None; color_names.len()];
+        for step in path
+        {
+            let from_wrap = wraps[step.color_idx].unwrap_or(step.wrap);
+            let (from, to) = tangent_chord(pins[step.from_idx], pins[step.to_idx], from_wrap, step.wrap, nail_radius);
+            wraps[step.color_idx] = Some(step.wrap);
+            let color = &mut colors[step.color_idx];
+            color.steps += 1;
+            color.length_px += ((to.0 - from.0).powi(2) + (to.1 - from.1).powi(2)).sqrt();
+            //Every string ends by wrapping around its last pin, the next string of the color starts there
+            color.wraps += 1;
+            pin_wraps[step.to_idx] += 1;
+        }
+        if let Some(board) = board
+        {
+            let wrap_mm = board.wrap_length_mm();
+            for color in colors.iter_mut()
+            {
+                color.length_mm = Some(color.length_px * board.mm_per_pixel + color.wraps as f32 * wrap_mm);
+                color.cost = color.thread.as_ref().and_then(|t| t.price_per_m).zip(color.length_mm).map(|(price, mm)| price * mm / 1000.);
+            }
+        }
+        PathReport
+        {
+            step_count: path.len(),
+            total_length_px: colors.iter().map(|c| c.length_px).sum(),
+            total_length_mm: board.map(|_| colors.iter().filter_map(|c| c.length_mm).sum()),
+            total_cost: colors.iter().map(|c| c.cost).sum(),
+            colors,
+            pin_wraps,
+            quality: None,
+            stop_reason: None
+        }
+    }
+
+    pub fn to_text(&self) -> String
+    {
+        let mut text = String::new();
+        let length = |px: f32, mm: Option<f32>| match mm
+        {
+            Some(mm) => format!("{:.2} m ({px:.0} px)", mm / 1000.),
+            None => format!("{px:.0} px")
+        };
+        writeln!(text, "Steps: {}", self.step_count).unwrap();
+        if let Some(reason) = self.stop_reason
+        {
+            writeln!(text, "Stopped because {reason}").unwrap();
+        }
+        writeln!(text, "Total thread: {}", length(self.total_length_px, self.total_length_mm)).unwrap();
+        if let Some(cost) = self.total_cost
+        {
+            writeln!(text, "Total cost: {cost:.2}").unwrap();
+        }
+        if let Some(quality) = self.quality
+        {
+            writeln!(text, "Quality: mean ΔE {:.2}, SSIM {:.4}, PSNR {:.2} dB", quality.mean_delta_e, quality.ssim, quality.psnr).unwrap();
+        }
+        for color in self.colors.iter()
+        {
+            let name = match &color.thread
+            {
+                Some(thread) => format!("{} {} {}", thread.manufacturer, thread.code, thread.name),
+                None => color.name.clone()
+            };
+            let cost = color.cost.map_or(String::new(), |cost| format!(", costing {cost:.2}"));
+            writeln!(text, "  {name}: {} over {} strings{cost}", length(color.length_px, color.length_mm), color.steps).unwrap();
+        }
+        writeln!(text, "Wraps per pin:").unwrap();
+        for (pin, wraps) in self.pin_wraps.iter().enumerate()
+        {
+            writeln!(text, "  {pin}: {wraps}").unwrap();
+        }
+        text
+    }
+
+    pub fn save_text(&self, path: &str) -> Result<(), String>
+    {
+        std::fs::write(path, self.to_text()).map_err(|e| format!("{path}: {e}"))
+    }
+
+    pub fn save_json(&self, path: &str) -> Result<(), String>
+    {
+        let file = File::create(path).map_err(|e| format!("{path}: {e}"))?;
+        serde_json::to_writer_pretty(file, self).map_err(|e| format!("{path}: {e}"))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::string_path::board::WrapDirection;
+
+    #[test]
+    fn lengths_are_summed_per_color()
+    {
+        let pins = [(0., 0.), (30., 40.), (30., 0.)];
+        let step = |from_idx, to_idx, color_idx| PathStep {from_idx, to_idx, color_idx, score: 0., wrap: WrapDirection::Clockwise};
+        let path = [step(0, 1, 0), step(0, 2, 1), step(1, 2, 0)];
+        let names = ["Black".to_string(), "Red".to_string()];
+        let report = PathReport::new(&path, &pins, &names, &[], None);
+        assert_eq!(report.colors[0].length_px, 90.);
+        assert_eq!(report.colors[1].length_px, 30.);
+        assert_eq!(report.pin_wraps, vec![0, 1, 2]);
+        assert_eq!(report.total_length_mm, None);
+
+        let board = Board {mm_per_pixel: 2., nail_diameter_mm: 0., thread_thickness_mm: 0.2, thread_opacity: 1.};
+        let thread = |price_per_m| Thread {manufacturer: "Acme".to_string(), code: "1".to_string(), name: "Black".to_string(), lab: [0., 0., 0.], price_per_m};
+        let report = PathReport::new(&path, &pins, &names, &[thread(Some(0.5)), thread(Some(1.))], Some(&board));
+        assert_eq!(report.total_length_mm, Some(240.));
+        assert!((report.total_cost.unwrap() - 0.15).abs() < 1e-6);
+        let report = PathReport::new(&path, &pins, &names, &[thread(Some(0.5)), thread(None)], Some(&board));
+        assert_eq!(report.total_cost, None);
+    }
+}
@@ -0,0 +1,43 @@
+use super::string_setting::{StringSettings, read_string_settings};
+
+use image::{Rgb, RgbImage};
+
+/*A dark disc left of a blue band on white, drawn at any size so that paths can be generated without an image file.
+    The tests, benchmarks and metric comparison all run on it.
+ */
+pub fn sample_image(size: u32) -> RgbImage
+{
+    let scale = size as f32 / 64.;
+    RgbImage::from_fn(size, size, |x, y|
+    {
+        let (x, y) = (x as f32 / scale, y as f32 / scale);
+        let d = (x - 24.).powi(2) + (y - 36.).powi(2);
+        if d < 150. {Rgb([20, 20, 20])} else if x > 40. {Rgb([40, 40, 200])} else {Rgb([250, 250, 250])}
+    })
+}
+
+/*Write the sample image and a settings file for it to a directory of the given name under the temporary directory,
+    returning the settings read back from it. Black and blue strings are drawn on 24 pins for 60 lines, the extra
+    settings are appended and may replace pin_count and line_count.
+ */
+pub fn sample_settings(name: &str, size: u32, extra: &str) -> Result<StringSettings, String>
+{
+    let dir = std::env::temp_dir().join(format!("stringwind_{name}"));
+    std::fs::create_dir_all(&dir).map_err(|e| format!("{}: {e}", dir.display()))?;
+    let image_path = dir.join("input.png");
+    sample_image(size).save(&image_path).map_err(|e| format!("{}: {e}", image_path.display()))?;
+    let default = |key: &str, value: &str| if extra.lines().any(|l| l.starts_with(key)) {String::new()} else {format!("{key} = {value}")};
+    let settings_path = dir.join("settings.toml");
+    std::fs::write(&settings_path, format!(
+r#"in_image_path = "{image}"
+out_image_path = "{out}/"
+{pin_count}
+pin_radius = 0.8
+{line_count}
+str_colors = [[0,0,0],[0,0,1]]
+bg_color = [1,1,1]
+edge_weight = 0.4
+{extra}"#, image = image_path.display(), out = dir.display(), pin_count = default("pin_count", "24"), line_count = default("line_count", "60")))
+        .map_err(|e| format!("{}: {e}", settings_path.display()))?;
+    read_string_settings(&settings_path).map_err(|e| e.to_string())
+}
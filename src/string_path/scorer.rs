@@ -0,0 +1,160 @@
+use crate::image_module::lab::{LabImageBuffer, ColorMetric};
+use super::line_table::LinePixel;
+use super::layers::Layers;
+
+use line_drawing::XiaolinWu;
+use palette::{Lab, Mix};
+use rayon::prelude::*;
+
+//Rounds of the per-pixel search for the coverage of each color which mixes into the input
+const SEPARATION_ROUNDS : usize = 20;
+
+//What a scorer sees of the path being generated
+pub struct ScoreContext<'a>
+{
+    pub input : &'a LabImageBuffer,
+    pub drawn : &'a LabImageBuffer,
+    pub layers : Option<&'a Layers>,
+    pub colors : &'a [Lab],
+    pub line_strength : f32
+}
+
+/*How much drawing a string of a color over a pixel would improve the image. A line's score is the weighted mean over its
+    pixels, and only lines scoring above 0 improve the image.
+ */
+pub trait Scorer: Send + Sync
+{
+    fn score_at_pixel(&self, context: &ScoreContext, pixel: &LinePixel, color_idx: usize) -> f32;
+
+    //Called after a string of the given color was drawn, for scorers keeping their own image of what is left to draw
+    fn string_drawn(&mut self, _color_idx: usize, _from: (f32, f32), _to: (f32, f32), _strength: f32) {}
+}
+
+//Improvement in similarity to the input from drawing the line over the pixel, either alone or mixed with its neighbours
+pub struct LabSimilarity
+{
+    pub edge_weight : f32,
+    pub metric : ColorMetric
+}
+
+impl Scorer for LabSimilarity
+{
+    fn score_at_pixel(&self, context: &ScoreContext, pixel: &LinePixel, color_idx: usize) -> f32
+    {
+        let input = |idx| context.input.pixel_at(idx);
+        let drawn = |idx| context.drawn.pixel_at(idx);
+        let unmixed_input = input(pixel.pixel);
+        let mixed_input = unmixed_input.mix(&input(pixel.left).mix(&input(pixel.right), 0.5), self.edge_weight);
+        let unmixed_undrawn = drawn(pixel.pixel);
+        //A translucent string only partially covers what is already drawn, and layers above its color cover it
+        let line_color = &match context.layers
+        {
+            Some(layers) => layers.composite(pixel.pixel, Some((color_idx, context.line_strength))),
+            None if context.line_strength < 1. => unmixed_undrawn.mix(&context.colors[color_idx], context.line_strength),
+            None => context.colors[color_idx]
+        };
+        let drawn_neighbours = drawn(pixel.left).mix(&drawn(pixel.right), 0.5);
+        let mixed_undrawn = unmixed_undrawn.mix(&drawn_neighbours, self.edge_weight);
+        let mixed_drawn = line_color.mix(&drawn_neighbours, self.edge_weight);
+
+        let similarity = |a: &Lab, b: &Lab| self.metric.similarity(a, b);
+        let score_mixed = similarity(&mixed_drawn, &mixed_input) - similarity(&mixed_undrawn, &mixed_input);
+        let score_unmixed = similarity(line_color, &unmixed_input) - similarity(&unmixed_undrawn, &unmixed_input);
+        score_mixed.max(score_unmixed)
+    }
+}
+
+/*Coverage of each color still missing from each pixel, starting from how much of each color mixed over the background
+    makes up the input. Drawn strings subtract their coverage, and a line scores the residual along it minus the penalty.
+ */
+pub struct Residual
+{
+    residual : Vec<Vec<f32>>, //Per color, row by row
+    width : u32,
+    penalty : f32
+}
+
+impl Residual
+{
+    pub fn new(input: &LabImageBuffer, colors: &[Lab], background: &Lab, penalty: f32) -> Residual
+    {
+        Residual
+        {
+            residual: separate(input, colors, background),
+            width: input.width(),
+            penalty
+        }
+    }
+}
+
+impl Scorer for Residual
+{
+    fn score_at_pixel(&self, _context: &ScoreContext, pixel: &LinePixel, color_idx: usize) -> f32
+    {
+        self.residual[color_idx][pixel.pixel as usize] - self.penalty
+    }
+
+    fn string_drawn(&mut self, color_idx: usize, from: (f32, f32), to: (f32, f32), strength: f32)
+    {
+        for ((x, y), weight) in XiaolinWu::<f32, i32>::new(from, to)
+        {
+            self.residual[color_idx][y as usize * self.width as usize + x as usize] -= weight * strength;
+        }
+    }
+}
+
+/*Coverage in [0,1] of each color such that the colors mixed over the background come closest to each input pixel,
+    found by adjusting one color at a time. Returns the coverage of every pixel for each color.
+ */
+pub fn separate(input: &LabImageBuffer, colors: &[Lab], background: &Lab) -> Vec<Vec<f32>>
+{
+    let directions: Vec<[f32; 3]> = colors.iter().map(|c| [c.l - background.l, c.a - background.a, c.b - background.b]).collect();
+    let dot = |a: &[f32; 3], b: &[f32; 3]| a[0]*b[0] + a[1]*b[1] + a[2]*b[2];
+    let per_pixel: Vec<Vec<f32>> = input.as_raw().par_chunks(3).map(|pixel|
+    {
+        let target = [pixel[0] - background.l, pixel[1] - background.a, pixel[2] - background.b];
+        let mut coverage = vec![0_f32; colors.len()];
+        for _ in 0..SEPARATION_ROUNDS
+        {
+            for (c, direction) in directions.iter().enumerate()
+            {
+                let length_sq = dot(direction, direction);
+                if length_sq == 0. {continue};
+                let mixed: Vec<f32> = (0..3).map(|i| directions.iter().zip(&coverage).map(|(d, t)| d[i] * t).sum()).collect();
+                let error = [target[0] - mixed[0], target[1] - mixed[1], target[2] - mixed[2]];
+                coverage[c] = (coverage[c] + dot(direction, &error) / length_sq).clamp(0., 1.);
+            }
+        }
+        coverage
+    }).collect();
+    (0..colors.len()).map(|c| per_pixel.iter().map(|coverage| coverage[c]).collect()).collect()
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::image_module::lab::LabBuf;
+
+    #[test]
+    fn residual_shrinks_where_strings_are_drawn()
+    {
+        let white = Lab::new(100., 0., 0.);
+        let (black, blue) = (Lab::new(0., 0., 0.), Lab::new(30., 70., -100.));
+        //Half grey, half a mix of blue and black
+        let mut input = LabImageBuffer::from_lab(8, 8, &white.mix(&black, 0.5));
+        for y in 4..8 {for x in 0..8 {input.put_pixel(x, y, &white.mix(&blue, 0.6).mix(&black, 0.2))}};
+        let coverage = separate(&input, &[black, blue], &white);
+        assert!((coverage[0][0] - 0.5).abs() < 0.01 && coverage[1][0] < 0.01, "{} {}", coverage[0][0], coverage[1][0]);
+        assert!(coverage[0][63] > 0.1 && coverage[1][63] > 0.4, "{} {}", coverage[0][63], coverage[1][63]);
+
+        let mut residual = Residual::new(&input, &[black, blue], &white, 0.);
+        let context = ScoreContext {input: &input, drawn: &input, layers: None, colors: &[black, blue], line_strength: 0.25};
+        let pixel = LinePixel {pixel: 2, weight: 1., left: 2, right: 2};
+        assert!((residual.score_at_pixel(&context, &pixel, 0) - 0.5).abs() < 0.01);
+        residual.string_drawn(0, (0., 0.), (7., 0.), 0.25);
+        residual.string_drawn(0, (0., 0.), (7., 0.), 0.25);
+        assert!((residual.score_at_pixel(&context, &pixel, 0)).abs() < 0.01);
+        assert!(residual.score_at_pixel(&context, &pixel, 1) <= 0.);
+    }
+}
@@ -1,28 +1,96 @@
 use crate::{
     tri_vec::TriVec,
-    image_module::lab::{LabImageBuffer, LabaImageBuffer, LabBuf, LabDifference, get_color_name},
+    image_module::quantize::kmeans_palette,
+    image_module::lab::{LabImageBuffer, LabBuf, ColorMetric},
+    image_module::color_names::ColorNames,
+    image_module::evaluation::{Evaluation, evaluate, error_map, viewing_blur_sigma, save_quality_log},
 };
-use super::string_setting::StringSettings;
+use super::string_setting::{StringSettings, ScoreInvalidation, RgbColor, NoMovePolicy, Layering, ScorerKind, PinLayoutKind};
+use super::export::{WindingInstructions, PinPosition, ColorThread, WindingStep};
+use super::pin_layout::{PinLayout, frame_side_pairs, close_pairs};
+use super::checkpoint::Checkpoint;
+use super::line_index::LineIndex;
+use super::line_table::{LineTable, LinePixel, line_pixels};
+use super::board::{Board, WrapDirection, ChordSides, output_dimensions};
+use super::report::PathReport;
+use super::layers::Layers;
+use super::refine::{CellImage, Refiner, RefineLimits, RefineSummary};
+use super::scorer::{Scorer, ScoreContext, LabSimilarity, Residual};
+use super::thread_catalog::{ThreadCatalog, Thread};
 
 use std::path::Path;
+use std::sync::Arc;
 use rand::distributions::{WeightedIndex,Distribution};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
 use geo::{Line, coord, algorithm::line_intersection::line_intersection, LineIntersection};
-use image:: {RgbaImage, Rgba, Rgba32FImage, ImageResult, EncodableLayout, DynamicImage};
-use palette::{Lab, Laba, Mix};
-use line_drawing::XiaolinWu;
-use show_image::{ImageView, ImageInfo, create_window};
+use palette::{Lab, Laba};
+use serde::{Serialize, Deserialize};
+use rayon::prelude::*;
 
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub struct PathStep
 {
     pub from_idx : usize,
     pub to_idx : usize,
     pub color_idx : usize,
-    pub score : f32
+    pub score : f32,
+    pub wrap : WrapDirection //Way the thread is wound around the pin it reaches
 }
 
-#[derive(Default, Clone, PartialEq)]
+//Why a path stopped before or at line_count
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StopReason
+{
+    LineCount,
+    ScoreThreshold,
+    QualityPlateau,
+    ThreadBudget
+}
+
+impl std::fmt::Display for StopReason
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result
+    {
+        match self
+        {
+            StopReason::LineCount => write!(f, "reached line_count"),
+            StopReason::ScoreThreshold => write!(f, "the best score of every color is below min_score"),
+            StopReason::QualityPlateau => write!(f, "mean ΔE stopped improving over plateau_window steps"),
+            StopReason::ThreadBudget => write!(f, "the next string would exceed thread_budget_m")
+        }
+    }
+}
+
+//Why no step could be added to the path. Every error is final, stepping again returns it again.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepError
+{
+    //A stopping rule ended the path
+    Stopped(StopReason),
+    //Every line from the current pins of the colors still being drawn is banned
+    NoAllowedMove,
+    //No color has a line with a positive score left, and no_move_policy does not draw one anyway
+    NoImprovingMove
+}
+
+impl std::fmt::Display for StepError
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result
+    {
+        match self
+        {
+            StepError::Stopped(reason) => write!(f, "{reason}"),
+            StepError::NoAllowedMove => write!(f, "every line from the current pins is banned"),
+            StepError::NoImprovingMove => write!(f, "no color has a line left which improves the image")
+        }
+    }
+}
+
+#[derive(Default, Clone, PartialEq, Serialize, Deserialize)]
 pub enum StringCombo
 {
     AllowedScored(f32),
@@ -31,7 +99,6 @@ pub enum StringCombo
     Banned
 }
 
-#[derive(Default)]
 pub struct StringPath
 {
     pub path : Vec<PathStep>, //Each element of vector is (fron_index, to_index, color_index)
@@ -41,38 +108,99 @@ pub struct StringPath
     input_image : LabImageBuffer, //Input image in Lab color space
     output_path : String,
     colors : Vec<Lab>,
-    _background : Lab,
+    background : Lab,
     path_length : usize,
     //Internally generated
-    combo_scores : TriVec<Vec<StringCombo>>,
+    combo_scores : TriVec<Vec<StringCombo>>, //Per pair, the score of each side for each color
+    sides : ChordSides, //Strings each pair of pins can be connected by, given the size of the nails
+    line_index : Option<LineIndex>, //Not built when every pair is checked for intersections instead
+    line_table : Option<LineTable>, //Not built when it would exceed the memory budget
+    board : Option<Board>,
+    threads : Vec<Thread>, //Thread used for each color, empty without a thread catalog
+    color_names : Arc<ColorNames>,
+    line_strength : f32, //How much a drawn string covers the pixels it passes through
+    quality_log : Vec<Evaluation>, //Quality every evaluation_interval steps
+    thread_used_mm : f32, //Zero without a board
+    ended_colors : Vec<bool>, //Colors which are not drawn anymore, under NoMovePolicy::EndColor
+    chord_uses : TriVec<Vec<usize>>, //Times each pair of pins was connected, per color
+    pin_wraps : Vec<usize>, //Times the thread was wrapped around each pin, over all colors
+    cur_wraps : Vec<Option<WrapDirection>>, //Way each color is wound around its current pin, None before its first step
+    layers : Option<Layers>, //Not kept when the strings are interleaved, as they are simply drawn over each other
+    scorer : Box<dyn Scorer>,
+    finished : Option<StepError>,
     pub strings_drawn : LabImageBuffer,
-    pub cur_step : usize,
+    pub cur_step : usize, //Strings placed so far
     pub cur_idxs : Vec<usize>,
     pub cur_scores : Vec<f32>,
-    edge_weight : f32
+    edge_weight : f32,
+    metric : ColorMetric,
+    seed : u64,
+    rng : ChaCha8Rng, //Drives the choice between the best steps of each color
+    settings : StringSettings
 }
 
 impl StringPath
 {
-    pub fn new(settings: StringSettings) -> Result<StringPath, String>
+    pub fn new(mut settings: StringSettings) -> Result<StringPath, String>
     {
-        let background = *settings.get::<Lab>("bg_color")?;
-        let colors = settings.get::<Vec<Lab>>("str_colors")?.clone();
+        let input_image_path = settings.in_image_path.clone();
+        let input_image = LabImageBuffer::from_file(&input_image_path).map_err(|e| format!("{input_image_path}: {e}"))?;
+
+        //Replace the configured colors by ones picked from the image, which a thread catalog then turns into threads
+        let seed = settings.resolve_seed();
+        if let Some(palette_size) = settings.palette_size
+        {
+            let colors = kmeans_palette(&input_image, palette_size, &settings.background(), seed);
+            if colors.is_empty()
+            {
+                return Err(format!("{input_image_path}: no colors other than the background to pick a palette from."));
+            }
+            if colors.len() < palette_size
+            {
+                log::warn!("{input_image_path} only has {} colors other than the background.", colors.len());
+            }
+            settings.str_colors = colors.iter().map(RgbColor::from_lab).collect();
+        }
 
-        let pin_count = *settings.get::<usize>("pin_count")?;
-        let path_length = *settings.get::<usize>("line_count")?;
+        //Make pins
+        let pin_positions = PinLayout::from_settings(&settings)?.positions(input_image.dimensions())?;
+        StringPath::from_parts(settings, input_image, pin_positions)
+    }
 
-        let input_image_path = settings.get::<String>("in_image_path")?.clone();
-        let binder = LabImageBuffer::from_file(&input_image_path);
-        if binder.is_err()  {return Err(binder.is_err().to_string())}
+    //Same as new, but replaces the seed given in the settings
+    pub fn with_seed(settings: StringSettings, seed: u64) -> Result<StringPath, String>
+    {
+        StringPath::new(StringSettings {seed: Some(seed), ..settings})
+    }
+
+    //A path without strings, which can be drawn on but not scored
+    fn blank(mut settings: StringSettings, input_image: LabImageBuffer, pin_positions: Vec<(f32, f32)>) -> Result<StringPath, String>
+    {
+        //Only colors of purchasable threads are used if there is a catalog
+        let threads = match &settings.thread_catalog
+        {
+            Some(catalog) => ThreadCatalog::load(catalog)?.closest_threads(&settings.colors()),
+            None => Vec::new()
+        };
+        if !threads.is_empty()
+        {
+            settings.str_colors = threads.iter().map(|t| RgbColor::from_lab(&t.color())).collect();
+        }
+        let color_names = match &settings.color_names
+        {
+            Some(path) => Arc::new(ColorNames::load(path)?),
+            None => ColorNames::builtin()
+        };
+        let background = settings.background();
+        let colors = settings.colors();
 
-        let input_image = binder.unwrap();
+        let pin_count = pin_positions.len();
+        let path_length = settings.line_count;
 
-        let output_path = settings.get::<String>("out_image_path")?.clone();
+        let input_image_path = settings.in_image_path.clone();
+        let output_path = settings.out_image_path.clone();
         let dimensions = input_image.dimensions();
-        //Make pins
-        let pin_radius = *settings.get::<f32>("pin_radius")?;
-        let pin_positions = pin_circle(pin_count,pin_radius, input_image.dimensions());
+        let pin_radius = settings.pin_radius;
         let strings_drawn = LabImageBuffer::from_lab(
             dimensions.0,
             dimensions.1, 
@@ -87,7 +215,10 @@ impl StringPath
 
         let cur_idxs = vec![0;colors.len()];
         let cur_scores = vec![0.;colors.len()];
-        let edge_weight = *settings.get::<f32>("edge_weight")?;
+        let edge_weight = settings.edge_weight;
+        let seed = settings.resolve_seed();
+        let board = Board::from_settings(&settings, &pin_positions);
+        let sides = ChordSides::new(board.map_or(0., |board| board.nail_radius()));
         let mut sp = StringPath
         {
             path: Vec::new(),
@@ -96,82 +227,717 @@ impl StringPath
             input_image_path,
             input_image,
             output_path,
-            combo_scores : TriVec::new(pin_count, &vec![StringCombo::Banned; colors.len()]),
+            combo_scores : TriVec::new(pin_count, &vec![StringCombo::Banned; colors.len() * sides.count()]),
+            sides,
+            line_index : None,
+            line_table : None,
+            board,
+            threads,
+            color_names,
+            line_strength : 1.,
+            quality_log : Vec::new(),
+            thread_used_mm : 0.,
+            ended_colors : vec![false; colors.len()],
+            chord_uses : TriVec::new(pin_count, &vec![0; colors.len()]),
+            pin_wraps : vec![0; pin_count],
+            cur_wraps : vec![None; colors.len()],
+            layers : None,
+            scorer : Box::new(LabSimilarity {edge_weight, metric: settings.color_metric}),
+            finished : None,
             colors,
-            _background : background,
+            background,
             path_length,
             strings_drawn,
             cur_step: 0,
             cur_idxs,
             cur_scores,
-            edge_weight
+            edge_weight,
+            metric : settings.color_metric,
+            seed,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            settings
         };
-        sp.populate_allowed_combos();
+        sp.line_strength = sp.board.map_or(1., |board| board.line_strength());
+        if sp.settings.layering != Layering::Interleaved
+        {
+            let order = sp.settings.layer_order.clone().unwrap_or_else(|| (0..sp.colors.len()).collect());
+            if order.len() != sp.colors.len()
+            {
+                return Err(format!("layer_order lists {} colors, but there are {}.", order.len(), sp.colors.len()));
+            }
+            sp.layers = Some(Layers::new(order, &sp.colors, &sp.background, dimensions));
+        }
+        Ok(sp)
+    }
 
+    fn from_parts(settings: StringSettings, input_image: LabImageBuffer, pin_positions: Vec<(f32, f32)>) -> Result<StringPath, String>
+    {
+        let mut sp = StringPath::blank(settings, input_image, pin_positions)?;
+        sp.prepare_scores();
         Ok(sp)
     }
 
-    //Save a visual representation of the current path
-    pub fn save_visual(&self) -> ImageResult<()>
+    //Scores of every allowed line, and the tables and indices which speed up scoring them, only needed to generate a path
+    fn prepare_scores(&mut self)
+    {
+        let (pin_count, sides, dimensions) = (self.pin_positions.len(), self.sides, self.strings_drawn.dimensions());
+        self.scorer = self.new_scorer();
+        self.populate_allowed_combos();
+        let pairs: Vec<(usize, usize)> = (0..pin_count).flat_map(|x| (x+1..pin_count).map(move |y| (x, y)))
+            .filter(|&(x, y)| self.combo_scores.at(x, y).iter().any(|c| *c != StringCombo::Banned))
+            .collect();
+        let table_bytes = LineTable::estimate_bytes(&self.pin_positions, &pairs, sides);
+        let table_budget = self.settings.line_table_budget_mb * 1024 * 1024;
+        if table_bytes <= table_budget
+        {
+            self.line_table = Some(LineTable::new(&self.pin_positions, dimensions, &pairs, sides));
+        }
+        else if table_budget > 0
+        {
+            log::info!("The line pixel table would need about {} MB, more than the budget of {} MB. Lines are rasterized on the fly instead.",
+                table_bytes / (1024 * 1024), self.settings.line_table_budget_mb);
+        }
+        if self.settings.score_invalidation == ScoreInvalidation::Cells
+        {
+            self.line_index = Some(LineIndex::new(&self.pin_positions, dimensions, pairs, sides));
+        }
+    }
+
+    //Save a visual representation of the current path, with a report of the thread it uses
+    pub fn save_visual(&self) -> Result<(), String>
+    {
+        let stem = self.output_stem();
+        self.render().save(&format!("{stem}.png")).map_err(|e| format!("{stem}.png: {e}"))?;
+        let report = self.report();
+        report.save_text(&format!("{stem}_report.txt"))?;
+        report.save_json(&format!("{stem}_report.json"))?;
+        error_map(&self.strings_drawn, &self.input_image, self.evaluation_blur()).save(format!("{stem}_error.png")).map_err(|e| format!("{stem}_error.png: {e}"))?;
+        if !self.quality_log.is_empty()
+        {
+            save_quality_log(&self.quality_log, &format!("{stem}_quality.csv"))?;
+        }
+        Ok(())
+    }
+
+    //How close the drawn strings are to the input image, as seen from the viewing distance
+    pub fn evaluate(&self) -> Evaluation
+    {
+        evaluate(self.path.len(), &self.strings_drawn, &self.input_image, self.evaluation_blur())
+    }
+
+    pub fn quality_log(&self) -> &[Evaluation]
+    {
+        &self.quality_log
+    }
+
+    //Blur hiding what the eye cannot resolve from the viewing distance, none if it is not set
+    fn evaluation_blur(&self) -> f32
+    {
+        match (self.settings.viewing_distance_mm, self.board)
+        {
+            (Some(distance), Some(board)) => viewing_blur_sigma(distance, board.mm_per_pixel),
+            _ => 0.
+        }
+    }
+
+    pub fn color_name(&self, color: &Lab) -> String
+    {
+        self.color_names.nearest(color).to_string()
+    }
+
+    pub fn report(&self) -> PathReport
+    {
+        let color_names: Vec<String> = self.colors.iter().map(|c| self.color_name(c)).collect();
+        let mut report = PathReport::new(&self.path, &self.pin_positions, &color_names, &self.threads, self.board.as_ref());
+        report.quality = Some(self.evaluate());
+        report.stop_reason = self.finished;
+        report
+    }
+
+    //The drawn strings, or the strings as real thread on the board if it is known
+    pub fn render(&self) -> LabImageBuffer
+    {
+        let input_dimensions = self.strings_drawn.dimensions();
+        match self.board
+        {
+            Some(board) => board.render(&self.winding_order(), &self.pin_positions, &self.colors, &self.background, input_dimensions, output_dimensions(&self.settings, input_dimensions)),
+            None => LabImageBuffer::from_raw(input_dimensions.0, input_dimensions.1, self.strings_drawn.as_raw().to_vec()).unwrap()
+        }
+    }
+
+    //Steps in the order they are wound, each color after the colors of the layers below it
+    pub fn winding_order(&self) -> Vec<PathStep>
+    {
+        let mut steps = self.path.clone();
+        if let Some(layers) = &self.layers
+        {
+            steps.sort_by_key(|step| layers.rank(step.color_idx));
+        }
+        steps
+    }
+
+    //Save the current path as winding instructions, both as JSON and as CSV
+    pub fn save_instructions(&self) -> Result<(), String>
+    {
+        let instructions = self.to_instructions()?;
+        let stem = self.output_stem();
+        instructions.save_json(&format!("{stem}.json"))?;
+        instructions.save_csv(&format!("{stem}.csv"))
+    }
+
+    //Output file path shared by every artifact of this path, without extension
+    fn output_stem(&self) -> String
     {
         let prefix = Path::new(&self.input_image_path).file_prefix().unwrap().to_str().unwrap();
         let color_names : Vec<String> =  self.colors.iter()
-            .map(|c| get_color_name(c)).collect();
+            .map(|c| self.color_name(c)).collect();
         let name_string = color_names.iter().fold("".to_string(),|a,b| format!("{a},{b}"));
-        let path = format!("{output_path}{prefix}_edgeweight:{edge_weight}_lines:{cur_step}{name_string}.png", output_path = self.output_path, edge_weight = self.edge_weight, cur_step = self.cur_step);
-        self.strings_drawn.save(&path)
-    } 
+        format!("{output_path}{prefix}_edgeweight:{edge_weight}_lines:{cur_step}_seed:{seed}{name_string}", output_path = self.output_path, edge_weight = self.edge_weight, cur_step = self.cur_step, seed = self.seed)
+    }
+
+    //Describe the current path as per-color winding instructions
+    pub fn to_instructions(&self) -> Result<WindingInstructions, String>
+    {
+        let pins = self.pin_positions.iter().enumerate()
+            .map(|(index, &(x, y))| PinPosition {index, x, y})
+            .collect();
+        let order = self.layers.as_ref().map_or_else(|| (0..self.colors.len()).collect(), |layers| layers.order().to_vec());
+        let colors = order.iter()
+            .map(|&index|
+            {
+                let color = &self.colors[index];
+                let steps = self.path.iter().enumerate()
+                    .filter(|(_, step)| step.color_idx == index)
+                    .map(|(step_idx, step)| WindingStep
+                    {
+                        step: step_idx,
+                        from_pin: step.from_idx,
+                        to_pin: step.to_idx,
+                        score: step.score,
+                        wrap: step.wrap
+                    })
+                    .collect();
+                ColorThread::new(index, color, self.color_name(color), self.threads.get(index).cloned(), steps)
+            })
+            .collect();
+        Ok(WindingInstructions
+        {
+            version: WindingInstructions::VERSION,
+            image_dimensions: self.strings_drawn.dimensions(),
+            pin_radius: self.pin_radius,
+            pins,
+            colors,
+            step_count: self.path.len(),
+            seed: self.seed,
+            layer_order: self.layers.as_ref().map(|layers| layers.order().to_vec()),
+            settings: self.settings.clone()
+        })
+    }
+
+    //Rebuild a path from winding instructions, re-rendering the drawn strings.
+    //  The input image is reloaded if it still exists, so that generation can be continued.
+    pub fn from_instructions(instructions: &WindingInstructions) -> Result<StringPath, String>
+    {
+        StringPath::replay(instructions, true)
+    }
+
+    //Same as from_instructions, but only draws the strings, so that the path can be rendered and evaluated but not continued
+    pub fn render_instructions(instructions: &WindingInstructions) -> Result<StringPath, String>
+    {
+        StringPath::replay(instructions, false)
+    }
+
+    fn replay(instructions: &WindingInstructions, continuable: bool) -> Result<StringPath, String>
+    {
+        let settings = instructions.settings.clone();
+        settings.validate("winding instructions").map_err(|e| e.to_string())?;
+        let (width, height) = instructions.image_dimensions;
+        let background = settings.background();
+        let input_image_path = settings.in_image_path.clone();
+        let input_image = match LabImageBuffer::from_file(&input_image_path)
+        {
+            Ok(image) if image.dimensions() == instructions.image_dimensions => image,
+            _ =>
+            {
+                log::warn!("Input image {input_image_path} is unavailable, only rendering is possible.");
+                LabImageBuffer::from_lab(width, height, &background)
+            }
+        };
+        let pin_positions = instructions.pins.iter().map(|p| (p.x, p.y)).collect();
+        let mut sp = match continuable
+        {
+            true => StringPath::from_parts(settings, input_image, pin_positions)?,
+            false => StringPath::blank(settings, input_image, pin_positions)?
+        };
+        //The order of optimized layers is only known once the path is finished
+        if let (Some(layers), Some(order)) = (sp.layers.as_mut(), &instructions.layer_order)
+        {
+            if order.len() != sp.colors.len()
+            {
+                return Err(format!("The layer order lists {} colors, but there are {}.", order.len(), sp.colors.len()));
+            }
+            layers.set_order(order.clone(), &mut sp.strings_drawn);
+        }
+        for step in instructions.path_steps()
+        {
+            if step.from_idx >= sp.pin_positions.len() || step.to_idx >= sp.pin_positions.len() || step.color_idx >= sp.colors.len()
+            {
+                return Err(format!("Step from pin {} to pin {} in color {} is out of range.", step.from_idx, step.to_idx, step.color_idx));
+            }
+            let ends = sp.step_ends(&step);
+            sp.cur_idxs[step.color_idx] = step.to_idx;
+            sp.cur_wraps[step.color_idx] = Some(step.wrap);
+            sp.draw_string(step.color_idx, ends);
+            sp.path.push(step);
+        }
+        sp.cur_step = sp.path.len();
+        Ok(sp)
+    }
+
+    pub fn settings(&self) -> &StringSettings
+    {
+        &self.settings
+    }
+
+    pub fn input_image(&self) -> &LabImageBuffer
+    {
+        &self.input_image
+    }
+
+    //Save everything needed to continue generating this path to the checkpoint file
+    pub fn save_checkpoint(&self) -> Result<(), String>
+    {
+        Checkpoint
+        {
+            version: Checkpoint::VERSION,
+            settings_hash: self.settings.path_hash(),
+            seed: self.seed,
+            path: self.path.clone(),
+            cur_step: self.cur_step,
+            cur_idxs: self.cur_idxs.clone(),
+            cur_scores: self.cur_scores.clone(),
+            rng: self.rng.clone(),
+            combo_scores: self.combo_scores.clone(),
+            dimensions: self.strings_drawn.dimensions(),
+            strings_drawn: self.strings_drawn.as_raw().to_vec(),
+            quality_log: self.quality_log.clone(),
+            ended_colors: self.ended_colors.clone()
+        }.save(&self.settings.checkpoint_file())
+    }
+
+    //Continue a path from its latest checkpoint. Refuses to resume if the settings changed since the checkpoint was saved.
+    pub fn resume(mut settings: StringSettings) -> Result<StringPath, String>
+    {
+        let checkpoint_file = settings.checkpoint_file();
+        let checkpoint = Checkpoint::load(&checkpoint_file)?;
+        //A run without a seed continues with the one it drew, a given seed has to match like any other setting
+        settings.seed.get_or_insert(checkpoint.seed);
+        //Compared after loading, as derived settings such as an automatic palette are part of the hash
+        let mut sp = StringPath::new(settings)?;
+        if checkpoint.settings_hash != sp.settings.path_hash()
+        {
+            return Err(format!("{checkpoint_file}: the settings changed since the checkpoint was saved, refusing to resume."));
+        }
+        if checkpoint.dimensions != sp.strings_drawn.dimensions() || checkpoint.cur_idxs.len() != sp.colors.len() || checkpoint.ended_colors.len() != sp.colors.len()
+            || checkpoint.combo_scores.size != sp.pin_positions.len()
+        {
+            return Err(format!("{checkpoint_file}: the checkpoint does not match the input image or colors."));
+        }
+        sp.strings_drawn = LabImageBuffer::from_raw(checkpoint.dimensions.0, checkpoint.dimensions.1, checkpoint.strings_drawn)
+            .ok_or(format!("{checkpoint_file}: the drawn strings are incomplete."))?;
+        sp.path = checkpoint.path;
+        sp.cur_step = checkpoint.cur_step;
+        sp.cur_idxs = checkpoint.cur_idxs;
+        sp.cur_scores = checkpoint.cur_scores;
+        sp.rng = checkpoint.rng;
+        sp.combo_scores = checkpoint.combo_scores;
+        sp.quality_log = checkpoint.quality_log;
+        sp.ended_colors = checkpoint.ended_colors;
+        //Whatever reached its maximum is already banned in the checkpointed scores
+        for step in sp.path.iter()
+        {
+            sp.chord_uses.at(step.from_idx, step.to_idx)[step.color_idx] += 1;
+            sp.pin_wraps[step.to_idx] += 1;
+        }
+        //The layers and the scorer's own image are rebuilt rather than checkpointed, the drawn strings come out the same
+        if sp.layers.is_some()
+        {
+            let (width, height) = sp.strings_drawn.dimensions();
+            sp.strings_drawn = LabImageBuffer::from_lab(width, height, &sp.background);
+        }
+        sp.thread_used_mm = 0.;
+        for step in sp.path.clone().iter()
+        {
+            let ends = sp.step_ends(step);
+            sp.cur_wraps[step.color_idx] = Some(step.wrap);
+            sp.thread_used_mm += sp.string_length_mm(ends);
+            if sp.layers.is_some()
+            {
+                sp.draw_string(step.color_idx, ends);
+            }
+            else
+            {
+                sp.scorer.string_drawn(step.color_idx, ends.0, ends.1, sp.line_strength);
+            }
+        }
+        Ok(sp)
+    }
 
     //Add a step to the path
-    pub fn step(&mut self) -> bool
+    pub fn step(&mut self) -> Result<PathStep, StepError>
     {
-        self.cur_step+= 1;
-        if self.cur_step == self.path_length {return false};
+        if let Some(error) = self.finished {return Err(error)};
+        if self.cur_step >= self.path_length {return self.finish(StepError::Stopped(StopReason::LineCount))};
+        if self.quality_plateaued() {return self.finish(StepError::Stopped(StopReason::QualityPlateau))};
+
+        let next_steps: Vec<PathStep> = self.get_best_steps().into_iter().filter(|s| !self.ended_colors[s.color_idx]).collect();
+        let Some(best) = next_steps.first().copied() else
+        {
+            let ended = self.ended_colors.iter().any(|e| *e);
+            return self.finish(if ended {StepError::NoImprovingMove} else {StepError::NoAllowedMove});
+        };
+        if self.settings.min_score.is_some_and(|min| best.score < min) {return self.finish(StepError::Stopped(StopReason::ScoreThreshold))};
+        let (improving, not_improving): (Vec<PathStep>, Vec<PathStep>) = next_steps.into_iter().partition(|s| s.score > 0.);
+        if self.settings.no_move_policy == NoMovePolicy::EndColor
+        {
+            for step in not_improving.iter()
+            {
+                self.ended_colors[step.color_idx] = true;
+            }
+        }
+        let step = if !improving.is_empty()
+        {
+            let dist = WeightedIndex::new(improving.iter().map(|p| p.score.min(1.))).unwrap();
+            improving[dist.sample(&mut self.rng)]
+        }
+        else if self.settings.no_move_policy == NoMovePolicy::LeastBad
+        {
+            best
+        }
+        else
+        {
+            return self.finish(StepError::NoImprovingMove);
+        };
+        let ends = self.step_ends(&step);
+        let length_mm = self.string_length_mm(ends);
+        if self.settings.thread_budget_m.is_some_and(|budget| (self.thread_used_mm + length_mm) / 1000. > budget) {return self.finish(StepError::Stopped(StopReason::ThreadBudget))};
 
-        let next_steps = self.get_best_steps();
-        let dist = WeightedIndex::new(next_steps.iter().map(|p| p.score.clamp(0.,1.))).unwrap();
-        let mut rng = rand::thread_rng();
-        let step = next_steps[dist.sample(&mut rng)];
-            
         self.cur_idxs[step.color_idx] = step.to_idx;
-        let from_coord = self.pin_positions[step.from_idx];
-        let to_coord = self.pin_positions[step.to_idx];
-        self.strings_drawn.draw_line(from_coord, to_coord, &self.colors[step.color_idx], false);
+        self.cur_wraps[step.color_idx] = Some(step.wrap);
+        self.thread_used_mm += length_mm;
+        self.draw_string(step.color_idx, ends);
         self.path.push(step);
-        self.unscore_intersected(&step);
-        true
+        self.cur_step += 1;
+        self.unscore_affected(ends);
+        self.count_uses(&step);
+        if self.settings.evaluation_interval.is_some_and(|interval| self.path.len().is_multiple_of(interval))
+        {
+            let evaluation = self.evaluate();
+            self.quality_log.push(evaluation);
+        }
+        Ok(step)
+    }
+
+    /*Ends of the step's string, where it leaves its color's current pin and touches the pin it reaches. It leaves the
+        way the color was wound around its current pin, so it has to be called before the color moves on.
+     */
+    fn step_ends(&self, step: &PathStep) -> ((f32, f32), (f32, f32))
+    {
+        let from_wrap = self.cur_wraps[step.color_idx].unwrap_or(step.wrap);
+        self.sides.string_ends(self.pin_positions[step.from_idx], self.pin_positions[step.to_idx], from_wrap, step.wrap)
+    }
+
+    //Thread used by a string between the given ends, 0 without a board to measure it on
+    fn string_length_mm(&self, (from, to): ((f32, f32), (f32, f32))) -> f32
+    {
+        self.board.map_or(0., |board| board.string_length_mm(from, to))
     }
 
-    //Calculate tehe color / pin combination with the best score
+    //Draw a string of the given color over the strings drawn so far, or into its color's layer
+    fn draw_string(&mut self, color_idx: usize, (from, to): ((f32, f32), (f32, f32)))
+    {
+        match self.layers.as_mut()
+        {
+            Some(layers) => layers.draw_line(&mut self.strings_drawn, from, to, color_idx, self.line_strength),
+            None => self.strings_drawn.draw_translucent_line(from, to, &self.colors[color_idx], self.line_strength)
+        }
+        self.scorer.string_drawn(color_idx, from, to, self.line_strength);
+    }
+
+    fn new_scorer(&self) -> Box<dyn Scorer>
+    {
+        match self.settings.scorer
+        {
+            ScorerKind::LabSimilarity => Box::new(LabSimilarity {edge_weight: self.edge_weight, metric: self.metric}),
+            ScorerKind::Residual => Box::new(Residual::new(&self.input_image, &self.colors, &self.background, self.settings.residual_penalty))
+        }
+    }
+
+    /*Count the uses of the step's pins, banning the lines which would exceed max_chord_uses, max_chord_reuse or max_pin_wraps.
+        A chord which may still be reused is rescored like any other line it overlaps, against the pixels its earlier uses already darkened.
+     */
+    fn count_uses(&mut self, step: &PathStep)
+    {
+        let uses = self.chord_uses.at(step.from_idx, step.to_idx);
+        uses[step.color_idx] += 1;
+        let color_full = self.settings.max_chord_reuse.is_some_and(|max| uses[step.color_idx] > max);
+        let chord_full = self.settings.max_chord_uses.is_some_and(|max| uses.iter().sum::<usize>() >= max);
+        let scores = self.combo_scores.at(step.from_idx, step.to_idx);
+        if chord_full
+        {
+            scores.iter_mut().for_each(|c| *c = StringCombo::Banned);
+        }
+        else if color_full
+        {
+            let sides = self.sides.count();
+            scores[step.color_idx * sides..(step.color_idx + 1) * sides].fill(StringCombo::Banned);
+        }
+        self.pin_wraps[step.to_idx] += 1;
+        if let Some(max) = self.settings.max_pin_wraps
+        {
+            //The color which just left a full pin can now be kept from coming back as well
+            for pin in [step.from_idx, step.to_idx]
+            {
+                if self.pin_wraps[pin] >= max
+                {
+                    self.ban_wraps_around(pin);
+                }
+            }
+        }
+    }
+
+    //Ban the lines to the given pin for every color, except the colors still at the pin which have to leave it
+    fn ban_wraps_around(&mut self, pin: usize)
+    {
+        let sides = self.sides.count();
+        for other in (0..self.pin_positions.len()).filter(|&p| p != pin)
+        {
+            for (slot, c) in self.combo_scores.at(pin, other).iter_mut().enumerate()
+            {
+                if self.cur_idxs[slot / sides] != pin
+                {
+                    *c = StringCombo::Banned;
+                }
+            }
+        }
+    }
+
+    //Why the path stopped, if it did
+    pub fn finished(&self) -> Option<StepError>
+    {
+        self.finished
+    }
+
+    fn finish(&mut self, error: StepError) -> Result<PathStep, StepError>
+    {
+        self.finished = Some(error);
+        if self.settings.layering == Layering::Optimized
+        {
+            self.optimize_layer_order();
+        }
+        Err(error)
+    }
+
+    /*Move or skip pins of the finished path where that brings it closer to the input image, keeping each color's thread
+        continuous. The path is only replaced if it improved, and the drawn strings are redrawn from it.
+     */
+    pub fn refine(&mut self) -> Option<RefineSummary>
+    {
+        if self.settings.refine_iterations == 0 {return None};
+        let order = self.layers.as_ref().map_or_else(|| (0..self.colors.len()).collect(), |layers| layers.order().to_vec());
+        let image = CellImage::new(&self.input_image, self.settings.refine_downscale, order, &self.colors, &self.background, self.line_strength, self.metric);
+        let mut allowed = TriVec::new(self.pin_positions.len(), &true);
+        for (x, y) in self.banned_pairs()
+        {
+            allowed.set(x, y, false);
+        }
+        for x in 0..self.pin_positions.len()
+        {
+            allowed.set(x, x, false);
+        }
+        let limits = RefineLimits
+        {
+            allowed,
+            max_chord_uses: self.settings.max_chord_uses,
+            max_chord_reuse: self.settings.max_chord_reuse,
+            max_pin_wraps: self.settings.max_pin_wraps,
+            thread_budget_mm: self.settings.thread_budget_m.map(|m| m * 1000.),
+            board: self.board
+        };
+        let mut refiner = Refiner::new(&self.path, &self.pin_positions, self.sides, image, limits);
+        let summary = refiner.run(self.settings.refine_iterations, self.settings.refine_temperature, &mut self.rng);
+        if summary.error_after < summary.error_before
+        {
+            self.redraw(refiner.path());
+        }
+        Some(summary)
+    }
+
+    //Replace the path, drawing its strings again from a blank image, each wound around its pins the way it was before
+    fn redraw(&mut self, path: Vec<PathStep>)
+    {
+        let (width, height) = self.strings_drawn.dimensions();
+        self.strings_drawn = LabImageBuffer::from_lab(width, height, &self.background);
+        if let Some(layers) = &self.layers
+        {
+            self.layers = Some(Layers::new(layers.order().to_vec(), &self.colors, &self.background, (width, height)));
+        }
+        self.scorer = self.new_scorer();
+        self.cur_idxs.fill(0);
+        self.cur_wraps.fill(None);
+        self.chord_uses = TriVec::new(self.pin_positions.len(), &vec![0; self.colors.len()]);
+        self.pin_wraps.fill(0);
+        self.thread_used_mm = 0.;
+        self.path.clear();
+        for step in path
+        {
+            let ends = self.step_ends(&step);
+            self.cur_idxs[step.color_idx] = step.to_idx;
+            self.cur_wraps[step.color_idx] = Some(step.wrap);
+            self.chord_uses.at(step.from_idx, step.to_idx)[step.color_idx] += 1;
+            self.pin_wraps[step.to_idx] += 1;
+            self.thread_used_mm += self.string_length_mm(ends);
+            self.draw_string(step.color_idx, ends);
+            self.path.push(step);
+        }
+        self.cur_step = self.path.len();
+    }
+
+    //Restack the layers in the order closest to the input image
+    fn optimize_layer_order(&mut self)
+    {
+        if let Some(layers) = self.layers.as_mut()
+        {
+            let order = layers.best_order(&self.input_image, self.metric);
+            layers.set_order(order, &mut self.strings_drawn);
+        }
+    }
+
+    //Whether mean ΔE improved by less than plateau_min_improvement over the last plateau_window steps
+    fn quality_plateaued(&self) -> bool
+    {
+        let (Some(window), Some(latest)) = (self.settings.plateau_window, self.quality_log.last()) else {return false};
+        match self.quality_log.iter().rev().find(|e| e.step + window <= latest.step)
+        {
+            Some(earlier) => earlier.mean_delta_e - latest.mean_delta_e < self.settings.plateau_min_improvement,
+            None => false
+        }
+    }
+
+    //The best step of every color which has an allowed line, best first
     pub fn get_best_steps(&mut self) -> Vec<PathStep>
     {
-        let mut best_steps = Vec::<PathStep>::new();
-        for color_idx in 0..self.colors.len()
+        let starts: Vec<(usize, usize, Option<WrapDirection>)> = (0..self.colors.len())
+            .map(|color_idx| (color_idx, self.cur_idxs[color_idx], self.cur_wraps[color_idx]))
+            .collect();
+        let mut best_steps = Vec::new();
+        for (color_idx, candidates) in self.candidate_steps(&starts).into_iter().enumerate()
         {
-            best_steps.push(self.get_best_step(color_idx));
-            self.cur_scores[color_idx] = best_steps.last().unwrap().score;
-        }   
-        best_steps.sort_by(|a,b| b.score.partial_cmp(&a.score).unwrap());
+            //Candidates come in pin order, clockwise first, so ties go to the lowest pin like a serial search
+            let mut best = candidates.into_iter().reduce(|best, step| if step.score > best.score {step} else {best});
+            if self.settings.lookahead_depth > 1 && best.is_some_and(|step| step.score > 0.)
+            {
+                best = self.look_ahead(color_idx).or(best);
+            }
+            //Colors without any allowed line have no step
+            self.cur_scores[color_idx] = best.map_or(f32::NEG_INFINITY, |step| step.score);
+            best_steps.extend(best);
+        }
+        best_steps.sort_by(|a,b| b.score.total_cmp(&a.score));
         best_steps
     }
 
-    //Calculate the best pin to move to for the given color
-    fn get_best_step(&mut self, color_idx : usize) -> PathStep
+    /*First step of the route of lookahead_depth strings from the color's current pin with the highest summed score, keeping
+        the beam_width best routes at each string. The first string has to improve the image by itself, like a greedy step.
+        Strings further ahead are scored as they would be now, ignoring what the strings before them would draw.
+     */
+    fn look_ahead(&mut self, color_idx: usize) -> Option<PathStep>
+    {
+        let (depth, beam_width) = (self.settings.lookahead_depth, self.settings.beam_width);
+        self.best_route(color_idx, depth, beam_width).and_then(|(steps, _)| steps.first().copied())
+    }
+
+    //Steps of the best route found by the beam search, with its summed score
+    fn best_route(&mut self, color_idx: usize, depth: usize, beam_width: usize) -> Option<(Vec<PathStep>, f32)>
     {
-        let from_idx = self.cur_idxs[color_idx];
-        let mut best_step = PathStep {from_idx, color_idx, to_idx : 0, score : -1.};
-        for to_idx in 0..self.pin_positions.len()
+        let mut routes: Vec<(Vec<PathStep>, f32)> = vec![(Vec::new(), 0.)];
+        for depth in 0..depth
         {
-            let pin_combo = (std::cmp::min(from_idx, to_idx), std::cmp::max(from_idx,to_idx));
-            let current_score_opt = self.calculate_current_score(color_idx, &pin_combo);
-            if current_score_opt.is_some() && current_score_opt.unwrap() > best_step.score
+            let starts: Vec<(usize, usize, Option<WrapDirection>)> = routes.iter().map(|(steps, _)| match steps.last()
             {
-                best_step.score  = current_score_opt.unwrap();
-                best_step.to_idx = if pin_combo.0 == from_idx {pin_combo.1} else {pin_combo.0};
-                best_step.color_idx = color_idx;
-            }            
+                Some(last) => (color_idx, last.to_idx, Some(last.wrap)),
+                None => (color_idx, self.cur_idxs[color_idx], self.cur_wraps[color_idx])
+            }).collect();
+            let mut longer = Vec::new();
+            for ((steps, total), candidates) in routes.iter().zip(self.candidate_steps(&starts))
+            {
+                for step in candidates.into_iter().filter(|s| depth > 0 || s.score > 0.)
+                {
+                    //Drawing the same string twice in a route would count its score twice
+                    let pair = |s: &PathStep| (s.from_idx.min(s.to_idx), s.from_idx.max(s.to_idx));
+                    if steps.iter().any(|s| pair(s) == pair(&step)) {continue};
+                    let mut route = steps.clone();
+                    route.push(step);
+                    longer.push((route, total + step.score));
+                }
+            }
+            if longer.is_empty() {break};
+            longer.sort_by(|a, b| b.1.total_cmp(&a.1));
+            longer.truncate(beam_width);
+            routes = longer;
         }
-        best_step
+        routes.into_iter().next().filter(|(steps, _)| !steps.is_empty())
+    }
+
+    /*Every allowed step of the given colors from the given pins, each pin reached either way round if the nails have a size.
+        The starts are each a color, its pin and the way it is wound around it. Candidates are scored in parallel, then the
+        new scores are written to the cache serially.
+     */
+    fn candidate_steps(&mut self, starts: &[(usize, usize, Option<WrapDirection>)]) -> Vec<Vec<PathStep>>
+    {
+        let sides = self.sides;
+        //Around point-like nails either way round is the same string, so the color keeps being wound the way it started
+        let wraps = |from_wrap: Option<WrapDirection>| match sides.count()
+        {
+            1 => vec![from_wrap.unwrap_or_default()],
+            _ => vec![WrapDirection::Clockwise, WrapDirection::CounterClockwise]
+        };
+        let mut candidates: Vec<(usize, PathStep, usize)> = Vec::new();
+        for (start_idx, &(color_idx, from_idx, from_wrap)) in starts.iter().enumerate()
+        {
+            for to_idx in 0..self.pin_positions.len()
+            {
+                for wrap in wraps(from_wrap)
+                {
+                    let side = sides.side(from_idx, to_idx, from_wrap.unwrap_or(wrap), wrap);
+                    candidates.push((start_idx, PathStep {from_idx, to_idx, color_idx, score: 0., wrap}, side));
+                }
+            }
+        }
+        let scores: Vec<(StringCombo, bool)> = candidates.par_iter()
+            .map(|(_, step, side)| self.current_score(step.color_idx, &(step.from_idx.min(step.to_idx), step.from_idx.max(step.to_idx)), *side))
+            .collect();
+        let mut steps = vec![Vec::new(); starts.len()];
+        for ((start_idx, mut step, side), (score, is_new)) in candidates.into_iter().zip(scores)
+        {
+            if let StringCombo::AllowedScored(s) = score
+            {
+                step.score = s;
+                steps[start_idx].push(step);
+            }
+            if is_new
+            {
+                let slot = self.slot(step.color_idx, side);
+                self.combo_scores.at(step.from_idx, step.to_idx)[slot] = score;
+            }
+        }
+        steps
+    }
+
+    //Index of the cached score of the given color's string on the given side of a pair
+    fn slot(&self, color_idx: usize, side: usize) -> usize
+    {
+        color_idx * self.sides.count() + side
     }
     
     //Calculate the initial score of every possible line
@@ -187,196 +953,530 @@ impl StringPath
                 }
             }
         }
+        for (x, y) in self.banned_pairs()
+        {
+            for c in self.combo_scores.at(x,y)
+            {
+                *c = StringCombo::Banned;
+            }
+        }
     }
     
+    //Pairs of pins which are never connected, whatever was drawn before
+    fn banned_pairs(&self) -> Vec<(usize, usize)>
+    {
+        //Strings along a straight side of the frame would only follow the frame, strings between close pins hug the rim
+        let close = close_pairs(&self.pin_positions, self.settings.min_pin_gap, self.settings.min_pin_angle_deg);
+        let sides = match self.settings.pin_layout
+        {
+            PinLayoutKind::Circle | PinLayoutKind::Ellipse => Vec::new(),
+            PinLayoutKind::Rectangle | PinLayoutKind::Polygon | PinLayoutKind::Custom => frame_side_pairs(&self.pin_positions)
+        };
+        sides.into_iter().chain(close).collect()
+    }
+
+    //Cached score of the given color's string on the given side of a pair, or its newly calculated score and true if it was unscored
+    fn current_score(&self, color_idx: usize, pin_combo: &(usize, usize), side: usize) -> (StringCombo, bool)
+    {
+        match self.combo_scores.get(pin_combo.0, pin_combo.1)[self.slot(color_idx, side)]
+        {
+            StringCombo::AllowedUnscored => (StringCombo::AllowedScored(self.calculate_score(color_idx, pin_combo, side)), true),
+            ref cached => (cached.clone(), false)
+        }
+    }
+
     //Calculate the current score of the given line (its similarity to the image vs the similarity without it)
-    fn calculate_current_score(&mut self, color_idx: usize, pin_combo: &(usize, usize)) -> Option<f32>
+    fn calculate_score(&self, color_idx: usize, pin_combo: &(usize, usize), side: usize) -> f32
     {
-        match self.combo_scores.at(pin_combo.0, pin_combo.1)[color_idx]
+        match &self.line_table
         {
-            StringCombo::AllowedScored(s) => return Some(s),
-            StringCombo::Banned => return None,
-            StringCombo::AllowedUnscored =>
+            Some(table) => self.score_pixels(table.get(pin_combo.0, pin_combo.1, side).iter().copied(), color_idx),
+            None =>
             {
-                let pin_a = self.pin_positions[pin_combo.0];
-                let pin_b = self.pin_positions[pin_combo.1];
-                let line = XiaolinWu::<f32, i32>::new(pin_a, pin_b);
-                let mut score_sum = 0_f32;
-                let mut weight_sum = 0_f32;
-                let line_color = self.colors[color_idx];
-                for ((x,y), weight) in line
-                {
-                    let score = self.score_at_point(&(x,y), &pin_a, &pin_b, &line_color) * weight;
-                    //println!("Score 1: {score}\nScore 2: {score_2}\n");
-                    score_sum += score;
-                    weight_sum += weight;
-                }
-                let score = score_sum / weight_sum;
-                self.combo_scores.at(pin_combo.0, pin_combo.1)[color_idx] = StringCombo::AllowedScored(score);
-                return Some(score)
+                let (start, end) = self.sides.ends(&self.pin_positions, *pin_combo, side);
+                self.score_pixels(line_pixels(start, end, self.input_image.dimensions()), color_idx)
             }
-        }        
+        }
     }
-    
-    fn score_at_point(&self, point: &(i32,i32), line_start: &(f32, f32), line_end: &(f32, f32), line_color: &Lab) -> f32
-    {
-        let mixed_input = StringPath::mix_at_point(line_start, line_end, point, &self.input_image, self.edge_weight).unwrap();
-        let unmixed_input = StringPath::mix_at_point(line_start, line_end, point, &self.input_image, 0.).unwrap();
-        let mixed_undrawn = StringPath::mix_at_point(line_start, line_end, point, &self.strings_drawn, self.edge_weight);
-        let unmixed_undrawn = StringPath::mix_at_point(line_start, line_end, point, &self.strings_drawn, 0.);
-        let mixed_drawn = StringPath::mix_line_at_point(line_start, line_end, point, &self.strings_drawn, self.edge_weight, line_color);
-        let unmixed_drawn = line_color;
 
-        let score_mixed = match mixed_undrawn
+    //Weighted mean of the scores of the given pixels
+    fn score_pixels(&self, pixels: impl Iterator<Item = LinePixel>, color_idx: usize) -> f32
+    {
+        let context = ScoreContext
         {
-            Some(mixed_undrawn_color) => 
-            {
-                mixed_drawn.similarity_to(&mixed_input) - mixed_undrawn_color.similarity_to(&mixed_input)
-            },
-            None => mixed_drawn.similarity_to(&mixed_input)
+            input: &self.input_image,
+            drawn: &self.strings_drawn,
+            layers: self.layers.as_ref(),
+            colors: &self.colors,
+            line_strength: self.line_strength
         };
-        let score_unmixed = match unmixed_undrawn
+        let mut score_sum = 0_f32;
+        let mut weight_sum = 0_f32;
+        for pixel in pixels
         {
-            Some(unmixed_undrawn_color) =>
+            score_sum += self.scorer.score_at_pixel(&context, &pixel, color_idx) * pixel.weight;
+            weight_sum += pixel.weight;
+        }
+        score_sum / weight_sum
+    }
+
+    //Mark the scores which drawing a string between the given ends may have changed for rescoring
+    fn unscore_affected(&mut self, (from, to): ((f32, f32), (f32, f32)))
+    {
+        match self.line_index.as_mut()
+        {
+            Some(index) =>
             {
-                unmixed_drawn.similarity_to(&unmixed_input) - unmixed_undrawn_color.similarity_to(&unmixed_input)
-            }
-            None => unmixed_drawn.similarity_to(&unmixed_input)
-        };
-        score_mixed.max(score_unmixed)
-        //let score_unmixed = line_color.similarity_to(&center_input) - center_undrawn.similarity_to(&center_input);
-        //let score_mixed = mix_drawn.similarity_to(&mix_input) - mix_undrawn.similarity_to(&mix_input);
-        ////Choose the best of the two scores 
-        //score_unmixed.max(score_mixed)
+                for (x, y) in index.affected(from, to)
+                {
+                    for c in self.combo_scores.at(x, y).iter_mut().filter(|c| **c != StringCombo::Banned)
+                    {
+                        *c = StringCombo::AllowedUnscored;
+                    }
+                }
+            },
+            None => self.unscore_intersected(Line::new(coord!{x: from.0, y: from.1}, coord!{x: to.0, y: to.1}))
+        }
     }
 
-    fn unscore_intersected(&mut self, step: &PathStep)
+    fn unscore_intersected(&mut self, drawn: Line<f32>)
     {
-        let inter_a = self.pin_positions[step.from_idx];
-        let inter_b = self.pin_positions[step.to_idx];
-        for color_idx in 0..self.colors.len()
+        let sides = self.sides.count();
+        for x in 0..self.pin_positions.len()
         {
-            for x in 0..self.pin_positions.len()
+            for y in x+1..self.pin_positions.len()
             {
-                for y in x+1..self.pin_positions.len()
+                for side in 0..sides
                 {
-                    if self.do_intersect(&(step.from_idx,step.to_idx), &(x,y), color_idx)
+                    if !self.do_intersect(drawn, (x, y), side) {continue};
+                    for c in self.combo_scores.at(x, y).iter_mut().skip(side).step_by(sides).filter(|c| **c != StringCombo::Banned)
                     {
-                        self.combo_scores.at(x,y)[color_idx] = StringCombo::AllowedUnscored;
+                        *c = StringCombo::AllowedUnscored;
                     }
                 }
             }
         }
     }
     
-    fn do_intersect(&mut self, combo_a: &(usize, usize), combo_b: &(usize, usize), color_idx : usize) -> bool
+    //Whether the drawn string crosses or overlaps the string on the given side of a pair
+    fn do_intersect(&self, drawn: Line<f32>, pair: (usize, usize), side: usize) -> bool
     {
-        if StringCombo::Banned == self.combo_scores.at(combo_a.0,combo_a.1)[color_idx] ||
-           StringCombo::Banned == self.combo_scores.at(combo_b.0,combo_b.1)[color_idx]
+        let (start, end) = self.sides.ends(&self.pin_positions, pair, side);
+        let line = Line::new(coord!{x: start.0, y: start.1}, coord!{x: end.0, y: end.1});
+        match line_intersection(drawn, line)
         {
-            return false;
+            Some(LineIntersection::SinglePoint { intersection: _, is_proper}) => is_proper,
+            Some(LineIntersection::Collinear { intersection: _ }) => true,
+            None => false
         }
+    }
+}
 
-        let from_a = self.pin_positions[combo_a.0];
-        let to_a   = self.pin_positions[combo_a.1];
-        let line_a = Line::new(coord!{x: from_a.0,y: from_a.1}, coord!{x: to_a.0,y: to_a.1});
+#[cfg(test)]
+pub(crate) mod tests
+{
+    extern crate test;
+    use super::{StringPath, StopReason, StepError, WrapDirection};
+    use crate::string_path::board::tangent_chord;
+    use crate::image_module::lab::{LabImageBuffer, LabBuf};
+    use crate::string_path::string_setting::{StringSettings, RgbColor};
+    use crate::string_path::sample::sample_settings;
 
-        let from_b = self.pin_positions[combo_b.0];
-        let to_b   = self.pin_positions[combo_b.1];
-        let line_b = Line::new(coord!{x: from_b.0,y: from_b.1}, coord!{x: to_b.0,y: to_b.1});
+    //Settings for the 64 pixel sample image, with the extra settings appended
+    pub fn test_settings(name: &str, extra: &str) -> StringSettings
+    {
+        sample_settings(name, 64, extra).unwrap()
+    }
+
+    //Generate a path on the test image until it stops
+    pub fn run_to_end(name: &str, extra: &str) -> StringPath
+    {
+        let mut sp = StringPath::new(test_settings(name, extra)).unwrap();
+        while sp.step().is_ok() {}
+        sp
+    }
 
-        let a_b_intersection = line_intersection(line_a, line_b);
-        if a_b_intersection.is_none() {return false};
-        let a_b_intersection = a_b_intersection.unwrap();
-        match a_b_intersection
+    #[test]
+    fn same_seed_same_path()
+    {
+        let sp_a = run_to_end("same_seed", "seed = 7");
+        let mut sp_b = StringPath::with_seed(sp_a.settings().clone(), 7).unwrap();
+        while sp_b.step().is_ok() {}
+        assert!(sp_a.path.iter().any(|s| s.color_idx == 1));
+        assert_eq!(sp_a.path, sp_b.path);
+        //The seed only decides between the colors' best steps, so another seed picks the colors in another order
+        let mut sp_c = StringPath::with_seed(sp_a.settings().clone(), 8).unwrap();
+        while sp_c.step().is_ok() {}
+        let colors = |sp: &StringPath| sp.path.iter().map(|s| s.color_idx).collect::<Vec<_>>();
+        assert_ne!(colors(&sp_a), colors(&sp_c));
+    }
+
+    #[test]
+    fn resumed_path_matches_uninterrupted()
+    {
+        //Strings between nail centers, then touching nails of a size, which the scores are cached for per side
+        for nails in ["", "board_diameter_mm = 100\nnail_diameter_mm = 3"]
         {
-            LineIntersection::SinglePoint { intersection: _, is_proper} => {return is_proper},
-            LineIntersection::Collinear { intersection: _ } => return true,
+            let uninterrupted = run_to_end("resume", &format!("seed = 3\n{nails}"));
+            let settings = uninterrupted.settings().clone();
+            let mut interrupted = StringPath::new(settings.clone()).unwrap();
+            for _ in 0..20 {interrupted.step().unwrap();}
+            interrupted.save_checkpoint().unwrap();
+            let mut resumed = StringPath::resume(settings.clone()).unwrap();
+            while resumed.step().is_ok() {}
+            assert_eq!(resumed.path, uninterrupted.path);
+
+            let changed = StringSettings {edge_weight: 0.2, ..settings.clone()};
+            assert!(StringPath::resume(changed).is_err());
+            assert!(StringPath::resume(StringSettings {seed: Some(4), ..settings.clone()}).is_err());
+
+            //Without a seed the checkpoint is found under the same name, and the run continues with the seed it drew
+            let mut random = StringPath::new(StringSettings {seed: None, ..settings.clone()}).unwrap();
+            for _ in 0..20 {random.step().unwrap();}
+            random.save_checkpoint().unwrap();
+            let mut resumed = StringPath::resume(StringSettings {seed: None, ..settings}).unwrap();
+            assert_eq!(resumed.seed, random.seed);
+            while resumed.step().is_ok() {}
+            while random.step().is_ok() {}
+            assert_eq!(resumed.path, random.path);
         }
     }
 
-    fn mix_at_point(start : &(f32,f32), end : &(f32, f32), center: &(i32, i32), image : &LabImageBuffer, edge_weight: f32) -> Option<Lab>
+    #[test]
+    fn rendering_instructions_skips_scoring()
     {
-        let center_color = image.get_pixel(center.0 as u32, center.1 as u32);
-        if edge_weight == 0.
-            {return Some(center_color)};
-        match StringPath::left_right_mix(start, end, center, image)
+        let sp = run_to_end("render_only", "seed = 3\nboard_diameter_mm = 100\nnail_diameter_mm = 3\nscore_invalidation = \"cells\"");
+        let rendered = StringPath::render_instructions(&sp.to_instructions().unwrap()).unwrap();
+        assert!(rendered.line_table.is_none() && rendered.line_index.is_none());
+        assert_eq!(rendered.render().as_raw(), sp.render().as_raw());
+        assert_eq!(rendered.evaluate(), sp.evaluate());
+    }
+
+    #[test]
+    fn parallel_scoring_matches_serial()
+    {
+        for invalidation in ["intersection", "cells"]
         {
-            Some(lr_color) =>
+            let run = |threads: usize|
             {
-                return Some(center_color.mix(&lr_color, edge_weight));
-            },
-            None => 
-            {
-                return Some(center_color);
-            }
+                let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+                pool.install(|| run_to_end("parallel", &format!("seed = 11\nscore_invalidation = \"{invalidation}\"")).path)
+            };
+            assert_eq!(run(1), run(4));
         }
     }
 
-    fn mix_line_at_point(start : &(f32,f32), end : &(f32, f32), center: &(i32, i32), image : &LabImageBuffer, edge_weight: f32, line_color: &Lab) -> Lab
+    #[test]
+    fn line_table_matches_rasterizing()
     {
-        let line_weight = 1. - edge_weight;
-        match StringPath::left_right_mix(start, end, center, image)
+        for nails in ["", "board_diameter_mm = 100\nnail_diameter_mm = 3"]
         {
-            Some(lr_color) =>
-            {
-                let line_lr_ratio = (edge_weight) / (edge_weight + line_weight);
-                return line_color.mix(&lr_color, line_lr_ratio);
-            },
-            None => 
+            let with_table = run_to_end("table", &format!("seed = 4\n{nails}"));
+            assert!(with_table.line_table.is_some());
+            let without_table = run_to_end("no_table", &format!("seed = 4\nline_table_budget_mb = 0\n{nails}"));
+            assert!(without_table.line_table.is_none());
+            assert_eq!(with_table.path, without_table.path);
+        }
+    }
+
+    #[test]
+    fn quality_is_logged()
+    {
+        let blank = StringPath::new(test_settings("quality", "seed = 6\nevaluation_interval = 20")).unwrap().evaluate();
+        let sp = run_to_end("quality", "seed = 6\nevaluation_interval = 20");
+        let log = sp.quality_log();
+        assert_eq!(log.iter().map(|e| e.step).collect::<Vec<_>>(), vec![20, 40, 60]);
+        assert!(log[1].mean_delta_e < blank.mean_delta_e, "{blank:?} {log:?}");
+        assert!(sp.report().to_text().contains("SSIM"));
+    }
+
+    #[test]
+    fn stopping_rules()
+    {
+        let rules = [
+            ("stop_line_count", "seed = 1", StopReason::LineCount),
+            ("stop_score", "seed = 1\nmin_score = 0.9", StopReason::ScoreThreshold),
+            ("stop_plateau", "seed = 1\nevaluation_interval = 5\nplateau_window = 5\nplateau_min_improvement = 100", StopReason::QualityPlateau),
+            ("stop_budget", "seed = 1\nboard_diameter_mm = 100\nthread_budget_m = 0.5", StopReason::ThreadBudget)
+        ];
+        let mut lengths = Vec::new();
+        for (name, extra, reason) in rules
+        {
+            let sp = run_to_end(name, extra);
+            let report = sp.report();
+            assert_eq!(report.stop_reason, Some(StepError::Stopped(reason)), "{name}");
+            assert_eq!(sp.finished(), report.stop_reason, "{name}");
+            assert!(report.to_text().contains(&reason.to_string()), "{name}");
+            lengths.push((sp.path.len(), report.total_length_mm));
+        }
+        assert_eq!(lengths[0].0, 60);
+        assert_eq!(lengths[1].0, 0);
+        assert_eq!(lengths[2].0, 10);
+        let used = lengths[3].1.unwrap();
+        assert!(used <= 500. && used > 400., "{used}");
+    }
+
+    #[test]
+    fn no_move_policies()
+    {
+        //The test image has nothing left to improve long before this many lines
+        let run = |policy: &str|
+        {
+            let mut sp = run_to_end(&format!("policy_{policy}"), &format!("seed = 8\nline_count = 1000\nno_move_policy = \"{policy}\""));
+            assert_eq!(sp.step(), Err(sp.finished().unwrap()));
+            sp
+        };
+        let skipped = run("skip_color");
+        assert_eq!(skipped.finished(), Some(StepError::NoImprovingMove));
+        assert!(skipped.path.iter().all(|s| s.score > 0.));
+        let ended = run("end_color");
+        assert_eq!(ended.finished(), Some(StepError::NoImprovingMove));
+        assert!(ended.path.len() <= skipped.path.len());
+        let least_bad = run("least_bad");
+        assert_eq!((least_bad.finished(), least_bad.path.len()), (Some(StepError::Stopped(StopReason::LineCount)), 1000));
+        assert!(least_bad.path.iter().any(|s| s.score <= 0.));
+    }
+
+    #[test]
+    fn pin_constraints_are_respected()
+    {
+        let sp = run_to_end("pin_constraints", "seed = 9\nline_count = 1000\nmin_pin_gap = 3\nmax_chord_uses = 1\nmax_pin_wraps = 3");
+        assert!(sp.path.len() > 10);
+        let pin_count = sp.pin_positions.len();
+        let mut chords = std::collections::HashSet::new();
+        let mut wraps = vec![0; pin_count];
+        for step in sp.path.iter()
+        {
+            let gap = step.from_idx.abs_diff(step.to_idx);
+            assert!(gap.min(pin_count - gap) >= 3, "{step:?}");
+            assert!(chords.insert((step.from_idx.min(step.to_idx), step.from_idx.max(step.to_idx))), "{step:?}");
+            wraps[step.to_idx] += 1;
+        }
+        assert!(wraps.iter().all(|w| *w <= 3), "{wraps:?}");
+    }
+
+    #[test]
+    fn chord_reuse()
+    {
+        //Translucent strings, so that drawing a chord again darkens it further
+        let mut sp = StringPath::new(test_settings("chord_reuse", "seed = 10\nline_count = 1000\nboard_diameter_mm = 100\nmax_chord_reuse = 2")).unwrap();
+        let step = sp.step().unwrap();
+        let chord = (step.from_idx.min(step.to_idx), step.from_idx.max(step.to_idx));
+        assert_eq!(sp.chord_uses.get(chord.0, chord.1)[step.color_idx], 1);
+        let side = sp.sides.side(step.from_idx, step.to_idx, step.wrap, step.wrap);
+        assert!(sp.calculate_score(step.color_idx, &chord, side) < step.score);
+
+        while sp.step().is_ok() {}
+        let mut uses = std::collections::HashMap::new();
+        for step in sp.path.iter()
+        {
+            *uses.entry((step.from_idx.min(step.to_idx), step.from_idx.max(step.to_idx), step.color_idx)).or_insert(0) += 1;
+        }
+        assert!(uses.values().all(|u| *u <= 3));
+        assert!(uses.values().any(|u| *u > 1));
+    }
+
+    #[test]
+    fn wraps_are_chosen_and_exported()
+    {
+        //Around point-like nails either way round is the same string, so every color keeps the default
+        let points = run_to_end("wraps_points", "seed = 12");
+        assert!(points.path.iter().all(|s| s.wrap == WrapDirection::Clockwise));
+
+        let sp = run_to_end("wraps", "seed = 12\nboard_diameter_mm = 100\nnail_diameter_mm = 3");
+        let mut first = StringPath::new(sp.settings().clone()).unwrap();
+        for step in first.get_best_steps()
+        {
+            //The step takes whichever side of the nails scores best
+            let pair = (step.from_idx.min(step.to_idx), step.from_idx.max(step.to_idx));
+            for wrap in [WrapDirection::Clockwise, WrapDirection::CounterClockwise]
             {
-                return *line_color;
+                let side = first.sides.side(step.from_idx, step.to_idx, wrap, wrap);
+                assert!(first.calculate_score(step.color_idx, &pair, side) <= step.score, "{step:?}");
             }
         }
+        assert!(sp.path.iter().any(|s| s.wrap == WrapDirection::Clockwise) && sp.path.iter().any(|s| s.wrap == WrapDirection::CounterClockwise));
+        let replayed = StringPath::from_instructions(&sp.to_instructions().unwrap()).unwrap();
+        assert_eq!(replayed.path, sp.path);
+        assert!(replayed.strings_drawn.as_raw().iter().zip(sp.strings_drawn.as_raw()).all(|(a, b)| (a - b).abs() < 1e-3));
     }
 
-    fn left_right_mix(start : &(f32,f32), end : &(f32, f32), center: &(i32, i32), image : &LabImageBuffer) -> Option<Lab>
+    #[test]
+    fn lookahead_changes_the_route()
     {
-        fn left_right_colors(start : &(f32,f32), end : &(f32, f32), center: &(i32, i32), image : &LabImageBuffer) -> (Option<Lab>, Option<Lab>)
+        let run = |name: &str, extra: &str| run_to_end(name, &format!("seed = 15\n{extra}")).path;
+        let greedy = run("greedy", "");
+        assert_eq!(run("lookahead_1", "lookahead_depth = 1\nbeam_width = 8"), greedy);
+        let ahead = run("lookahead_3", "lookahead_depth = 3\nbeam_width = 4");
+        assert_ne!(ahead, greedy);
+        assert!(ahead.iter().all(|s| s.score > 0.));
+
+        //Over the next three strings, the beam finds routes scoring at least as much as picking the best string each time
+        let mut sp = StringPath::new(test_settings("lookahead_total", "seed = 15\nlookahead_depth = 3\nbeam_width = 4")).unwrap();
+        let (mut compared, mut improved) = (0, 0);
+        while sp.step().is_ok()
         {
-            let diff = (end.0 - start.0, end.1 - start.1);
-            let len = (diff.0*diff.0 + diff.1*diff.1).sqrt();
-            let offset = ((diff.0 / len).round() as i32,  (diff.1 / len).round() as i32);
-            let l_coord = ((center.0 + offset.1) as u32, (center.1 - offset.0) as u32);
-            let r_coord  = ((center.0 - offset.1) as u32, (center.1 + offset.0) as u32);
-            let l_pix = image.get_pixel(l_coord.0, l_coord.1);
-            let r_pix = image.get_pixel(r_coord.0, r_coord.1);
-            let l_opt = Some(l_pix);
-            let r_opt = Some(r_pix);
-            (l_opt, r_opt)
+            for color_idx in 0..sp.colors.len()
+            {
+                let Some((greedy_steps, greedy_total)) = sp.best_route(color_idx, 3, 1) else {continue};
+                let (steps, total) = sp.best_route(color_idx, 3, 4).unwrap();
+                assert!(total >= greedy_total, "{steps:?} {total} {greedy_steps:?} {greedy_total}");
+                compared += 1;
+                improved += (total > greedy_total) as usize;
+            }
         }
-        let (l_opt,r_opt) = left_right_colors(start, end, center, image);
-        if l_opt.is_some() && r_opt.is_some()
+        assert!(compared > 20 && improved > 0, "{compared} {improved}");
+    }
+
+    #[test]
+    fn refinement_keeps_threads_continuous()
+    {
+        let mut sp = run_to_end("refine", "seed = 14\nline_count = 150\nno_move_policy = \"least_bad\"\nrefine_iterations = 2000\nmax_pin_wraps = 12\nboard_diameter_mm = 100\nnail_diameter_mm = 3");
+        let greedy = sp.path.clone();
+        let summary = sp.refine().unwrap();
+        assert!(summary.error_after < summary.error_before && summary.removed > 0, "{summary:?}");
+        assert_eq!(sp.path.len(), greedy.len() - summary.removed);
+        for color_idx in 0..sp.colors.len()
         {
-            let l_color = l_opt.unwrap();
-            let r_color = r_opt.unwrap();
-            let lr_mix = l_color.mix(&r_color, 0.5);
-            let lr_weight = 1.;//(l_weight + r_weight) / 2.;
-            return Some(lr_mix);
+            let route: Vec<_> = sp.path.iter().filter(|s| s.color_idx == color_idx).collect();
+            assert!(route.first().is_none_or(|s| s.from_idx == 0));
+            assert!(route.windows(2).all(|w| w[0].to_idx == w[1].from_idx), "{route:?}");
         }
-        else if l_opt.is_some()
+        assert!(sp.pin_wraps.iter().all(|w| *w <= 12), "{:?}", sp.pin_wraps);
+    }
+
+    #[test]
+    fn picked_colors_become_threads()
+    {
+        let sp = StringPath::new(test_settings("palette_threads", "seed = 17\npalette_size = 2\nthread_catalog = \"src/data/threads.csv\"")).unwrap();
+        assert_eq!(sp.threads.len(), 2);
+        let thread_colors: Vec<RgbColor> = sp.threads.iter().map(|t| RgbColor::from_lab(&t.color())).collect();
+        assert_eq!(sp.settings().str_colors, thread_colors);
+    }
+
+    #[test]
+    fn residual_scorer()
+    {
+        let run = |name: &str, extra: &str|
         {
-            return Some(l_opt.unwrap());
-        }
-        else if r_opt.is_some()
+            let sp = run_to_end(name, &format!("seed = 16\nline_count = 1000\n{extra}"));
+            let blank = StringPath::new(sp.settings().clone()).unwrap().evaluate();
+            assert!(sp.evaluate().mean_delta_e < blank.mean_delta_e);
+            sp
+        };
+        let similarity = run("scorer_similarity", "");
+        let residual = run("scorer_residual", "scorer = \"residual\"");
+        //Same seed and image, so any difference comes from the scorer
+        assert_eq!(residual.seed, similarity.seed);
+        assert_ne!(residual.path[0].score, similarity.path[0].score);
+        assert_ne!(residual.path, similarity.path);
+        assert!(residual.strings_drawn.as_raw().iter().zip(similarity.strings_drawn.as_raw()).any(|(a, b)| (a - b).abs() > 1.));
+        assert!(residual.path.iter().any(|s| s.color_idx == 1));
+        //A penalty only leaves the lines with the most residual along them
+        let penalized = run("scorer_penalty", "scorer = \"residual\"\nresidual_penalty = 0.3");
+        assert!(penalized.path.len() < residual.path.len(), "{} {}", penalized.path.len(), residual.path.len());
+    }
+
+    #[test]
+    fn layers_are_wound_in_order()
+    {
+        let sp = run_to_end("layers", "seed = 13\nlayering = \"ordered\"\nlayer_order = [1, 0]\nboard_diameter_mm = 100");
+        let instructions = sp.to_instructions().unwrap();
+        assert_eq!(instructions.colors.iter().map(|c| c.index).collect::<Vec<_>>(), vec![1, 0]);
+        let wound = sp.winding_order();
+        assert!(wound.windows(2).all(|w| w[0].color_idx == w[1].color_idx || w[0].color_idx == 1), "{wound:?}");
+
+        //Drawing the strings over each other in the order they are wound, touching the nails, gives the same image
+        let (width, height) = sp.strings_drawn.dimensions();
+        let mut stacked = LabImageBuffer::from_lab(width, height, &sp.background);
+        let mut wraps = vec![None; sp.colors.len()];
+        for step in wound.iter()
         {
-            return Some(r_opt.unwrap());
+            let from_wrap = wraps[step.color_idx].unwrap_or(step.wrap);
+            let (from, to) = tangent_chord(sp.pin_positions[step.from_idx], sp.pin_positions[step.to_idx], from_wrap, step.wrap, sp.board.unwrap().nail_radius());
+            wraps[step.color_idx] = Some(step.wrap);
+            stacked.draw_translucent_line(from, to, &sp.colors[step.color_idx], sp.line_strength);
         }
-        return None;
+        assert!(stacked.as_raw().iter().zip(sp.strings_drawn.as_raw()).all(|(a, b)| (a - b).abs() < 1e-3));
+
+        let optimized = run_to_end("layers_optimized", "seed = 13\nlayering = \"optimized\"");
+        let replayed = StringPath::from_instructions(&optimized.to_instructions().unwrap()).unwrap();
+        assert_eq!(replayed.layers.unwrap().order(), optimized.layers.unwrap().order());
+        assert!(replayed.strings_drawn.as_raw().iter().zip(optimized.strings_drawn.as_raw()).all(|(a, b)| (a - b).abs() < 1e-3));
     }
 
-}
+    /*Step throughput on a 256 pixel sample image with 150 pins, with each way of invalidating cached scores and each color metric.
+        least_bad keeps placing strings once none improves the image, so that every iteration times a real step.
+     */
+    fn bench_path(name: &str, extra: &str) -> StringPath
+    {
+        let settings = sample_settings(&format!("bench_{name}"), 256, &format!("pin_count = 150\nline_count = 1000000\nno_move_policy = \"least_bad\"\n{extra}")).unwrap();
+        let mut sp = StringPath::new(settings).unwrap();
+        //Score every line once, so that only the rescoring after each step is measured
+        sp.step().unwrap();
+        sp
+    }
 
-fn pin_circle(pin_count: usize, radius: f32, dimensions: (u32, u32)) -> Vec<(f32,f32)>
-{
-    let mut pins = vec![(0.,0.);pin_count];
-    assert!(radius > 0. && radius < 1.);
-    let center = ((dimensions.0/2) as f32, (dimensions.1/2) as f32);
-    for i in 0..pin_count
+    fn bench_steps(b: &mut test::Bencher, name: &str, extra: &str)
+    {
+        let mut sp = bench_path(name, extra);
+        b.iter(|| sp.step().unwrap());
+    }
+
+    //Only the invalidation after a step, without rescoring
+    fn bench_invalidation(b: &mut test::Bencher, invalidation: &str)
     {
-        let angle = std::f32::consts::PI * 2. * (i as f32) / pin_count as f32;
-        pins[i].0 = center.0 + angle.cos() * center.0 * radius;
-        pins[i].1 = center.1 + angle.sin() * center.1 * radius;
+        let mut sp = bench_path(&format!("invalidate_{invalidation}"), &format!("score_invalidation = \"{invalidation}\""));
+        let step = sp.path[0];
+        let ends = (sp.pin_positions[step.from_idx], sp.pin_positions[step.to_idx]);
+        b.iter(|| sp.unscore_affected(ends));
     }
-    return pins;
-}
\ No newline at end of file
+
+    #[bench]
+    fn step_cells(b: &mut test::Bencher)
+    {
+        bench_steps(b, "cells", "score_invalidation = \"cells\"");
+    }
+
+    #[bench]
+    fn step_intersection(b: &mut test::Bencher)
+    {
+        bench_steps(b, "intersection", "score_invalidation = \"intersection\"");
+    }
+
+    #[bench]
+    fn invalidate_cells(b: &mut test::Bencher)
+    {
+        bench_invalidation(b, "cells");
+    }
+
+    #[bench]
+    fn invalidate_intersection(b: &mut test::Bencher)
+    {
+        bench_invalidation(b, "intersection");
+    }
+
+    #[bench]
+    fn step_lookahead(b: &mut test::Bencher)
+    {
+        bench_steps(b, "lookahead", "lookahead_depth = 3\nbeam_width = 4");
+    }
+
+    #[bench]
+    fn step_cie94(b: &mut test::Bencher)
+    {
+        bench_steps(b, "cie94", "color_metric = \"cie94\"");
+    }
+
+    #[bench]
+    fn step_ciede2000(b: &mut test::Bencher)
+    {
+        bench_steps(b, "ciede2000", "color_metric = \"ciede2000\"");
+    }
+
+    #[bench]
+    fn step_luminance_weighted(b: &mut test::Bencher)
+    {
+        bench_steps(b, "luminance_weighted", "color_metric = \"luminance_weighted\"");
+    }
+}
@@ -1,168 +1,591 @@
-
-use std::collections::HashMap;
-use std::iter::repeat;
+use std::fmt;
+use std::path::Path;
 use palette::{Srgb, Lab, IntoColor};
-use config::{Config, ConfigError};
-pub trait StringSettingType
-{
-    fn get_setting<'a>(settings: &'a StringSettings, key: &str) -> Result<&'a Self, String>;
-}
-impl StringSettingType for usize
+use config::{Config, ConfigError, Environment, FileFormat};
+use serde::{Serialize, Deserialize};
+use crate::image_module::lab::ColorMetric;
+
+//Prefix of environment variables overriding settings, e.g. STRINGWIND_PIN_COUNT=300
+const ENV_PREFIX : &str = "STRINGWIND";
+
+//An sRGB color with components in [0,1], as written in settings files
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct RgbColor(pub [f32; 3]);
+
+impl RgbColor
 {
-    fn get_setting<'a>(settings : &'a StringSettings, key: &str) -> Result<&'a Self, String>
+    pub fn lab(&self) -> Lab
     {
-        match settings.size_vals.get(key)
-        {
-            Some(val) => Ok(val),
-            None => Err(format!("Key {key} not present in settings."))
-        }
+        Srgb::new(self.0[0], self.0[1], self.0[2]).into_color()
     }
 
-}
-impl StringSettingType for String
-{
-    fn get_setting<'a>(settings : &'a StringSettings, key: &str) -> Result<&'a Self, String>
+    //Colors outside of the sRGB gamut are clamped
+    pub fn from_lab(color: &Lab) -> RgbColor
     {
-        match settings.string_vals.get(key)
-        {
-            Some(val) => Ok(val),
-            None => Err(format!("Key {key} not present in settings."))
-        }    
+        let srgb: Srgb = (*color).into_color();
+        RgbColor([srgb.red, srgb.green, srgb.blue].map(|c| c.clamp(0., 1.)))
     }
 }
-impl StringSettingType for f32
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PinLayoutKind
 {
-    fn get_setting<'a>(settings : &'a StringSettings, key: &str) -> Result<&'a Self, String>
-    {
-        match settings.float_vals.get(key)
-        {
-            Some(val) => Ok(val),
-            None => Err(format!("Key {key} not present in settings."))
-        }   
-     }
+    #[default]
+    Circle,
+    Ellipse,
+    Rectangle,
+    Polygon,
+    Custom
 }
-impl StringSettingType for Lab
+
+//How cached line scores are invalidated after each step
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScoreInvalidation
 {
-    fn get_setting<'a>(settings : &'a StringSettings, key: &str) -> Result<&'a Self, String>
-    {  
-        match settings.lab_vals.get(key)
-        {
-            Some(val) => Ok(val),
-            None => Err(format!("Key {key} not present in settings."))
-        }
-    }
+    //Rescore the lines sharing a grid cell with the drawn string
+    Cells,
+    //Rescore the lines crossing the drawn string, checking every pin pair
+    #[default]
+    Intersection
 }
-impl StringSettingType for Vec<Lab>
+
+//How a line is scored
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScorerKind
 {
-    fn get_setting<'a>(settings : &'a StringSettings, key: &str) -> Result<&'a Self, String>
-    {
-        match settings.lab_vec_vals.get(key)
-        {
-            Some(val) => Ok(val),
-            None => Err("Key {key} not present in settings.".to_string())
-        }
-    }
+    //Improvement in similarity to the input of the pixels along the line, mixed with their neighbours by edge_weight
+    #[default]
+    LabSimilarity,
+    //Coverage of the line's color still missing along it, minus residual_penalty
+    Residual
+}
+
+//What to do with a color which has no line left improving the image
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NoMovePolicy
+{
+    //Draw the other colors, coming back to it when drawing them made one of its lines worth drawing
+    #[default]
+    SkipColor,
+    //Never draw the color again
+    EndColor,
+    //Draw its least bad line anyway if no color has an improving one
+    LeastBad
+}
+
+//How strings of different colors cover each other
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Layering
+{
+    //Each string covers every string drawn before it, as if the colors were wound alternately
+    #[default]
+    Interleaved,
+    //Each color is wound after the ones before it in layer_order, covering all of their strings
+    Ordered,
+    //Same as ordered, then the colors are restacked in the order closest to the input image once the path is finished
+    Optimized
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct StringSettings
 {
-    cfg: Config,
-    size_vals: HashMap<&'static str, usize>,
-    string_vals: HashMap<&'static str, String>,
-    float_vals: HashMap<&'static str, f32>,
-    lab_vals: HashMap<&'static str, Lab>,
-    lab_vec_vals: HashMap<&'static str, Vec<Lab>>
+    pub in_image_path : String,
+    pub out_image_path : String,
+    pub pin_count : usize,
+    pub line_count : usize,
+    #[serde(default)]
+    pub str_colors : Vec<RgbColor>,
+    #[serde(default = "default_pin_radius")]
+    pub pin_radius : f32,
+    #[serde(default = "default_edge_weight")]
+    pub edge_weight : f32,
+    #[serde(default = "default_bg_color")]
+    pub bg_color : RgbColor,
+    #[serde(default)]
+    pub width : Option<u32>,
+    #[serde(default)]
+    pub height : Option<u32>,
+    //A missing seed is drawn at random when the path is made, and stored with its checkpoints and instructions
+    #[serde(default)]
+    pub seed : Option<u64>,
+    #[serde(default)]
+    pub pin_layout : PinLayoutKind,
+    #[serde(default)]
+    pub pin_spacing : Option<f32>,
+    #[serde(default = "default_polygon_sides")]
+    pub polygon_sides : usize,
+    #[serde(default)]
+    pub pin_file : Option<String>,
+    //Steps between checkpoints, no checkpoints are written if not set
+    #[serde(default)]
+    pub checkpoint_interval : Option<usize>,
+    #[serde(default)]
+    pub checkpoint_path : Option<String>,
+    #[serde(default)]
+    pub score_invalidation : ScoreInvalidation,
+    #[serde(default)]
+    pub scorer : ScorerKind,
+    //Subtracted from the score of every line by the residual scorer, higher values draw fewer strings
+    #[serde(default)]
+    pub residual_penalty : f32,
+    //How the similarity of drawn strings to the input image is measured
+    #[serde(default)]
+    pub color_metric : ColorMetric,
+    //Memory the precomputed line pixels may use, 0 always rasterizes lines on the fly
+    #[serde(default = "default_line_table_budget_mb")]
+    pub line_table_budget_mb : usize,
+    //Physical board, spanning the pins. Strings are drawn as real thread if the board diameter is set.
+    #[serde(default)]
+    pub board_diameter_mm : Option<f32>,
+    #[serde(default = "default_nail_diameter_mm")]
+    pub nail_diameter_mm : f32,
+    #[serde(default = "default_thread_thickness_mm")]
+    pub thread_thickness_mm : f32,
+    #[serde(default = "default_thread_opacity")]
+    pub thread_opacity : f32,
+    //Number of colors to pick from the input image instead of using str_colors
+    #[serde(default)]
+    pub palette_size : Option<usize>,
+    //CSV of purchasable threads, every color, configured or picked from the image, is replaced by the closest thread
+    #[serde(default)]
+    pub thread_catalog : Option<String>,
+    //CSV of name, L, a, b used to name colors in file names and exports, instead of the built-in table
+    #[serde(default)]
+    pub color_names : Option<String>,
+    //Distance the finished board is seen from, details smaller than the eye resolves from there are blurred before evaluating
+    #[serde(default)]
+    pub viewing_distance_mm : Option<f32>,
+    //Log the quality of the drawn strings every this many steps
+    #[serde(default)]
+    pub evaluation_interval : Option<usize>,
+    //Stop before line_count once the best score of every color is below this
+    #[serde(default)]
+    pub min_score : Option<f32>,
+    //Stop once mean ΔE improved by less than plateau_min_improvement over this many steps, needs evaluation_interval
+    #[serde(default)]
+    pub plateau_window : Option<usize>,
+    #[serde(default = "default_plateau_min_improvement")]
+    pub plateau_min_improvement : f32,
+    //Stop before the thread of all colors together gets longer than this, needs board_diameter_mm
+    #[serde(default)]
+    pub thread_budget_m : Option<f32>,
+    #[serde(default)]
+    pub no_move_policy : NoMovePolicy,
+    //Strings between pins closer than this around the outline are never drawn, 2 forbids neighbouring pins
+    #[serde(default = "default_min_pin_gap")]
+    pub min_pin_gap : usize,
+    //Strings between pins closer than this angle, seen from the center of the pins, are never drawn
+    #[serde(default)]
+    pub min_pin_angle_deg : f32,
+    //Times the same pair of pins may be connected, over all colors
+    #[serde(default)]
+    pub max_chord_uses : Option<usize>,
+    //Times a color may connect the same pair of pins again after the first time, 0 draws every chord at most once per color
+    #[serde(default)]
+    pub max_chord_reuse : Option<usize>,
+    //Times the thread may be wrapped around a single pin, over all colors
+    #[serde(default)]
+    pub max_pin_wraps : Option<usize>,
+    //Strings ahead each step is chosen for, 1 takes the best next string
+    #[serde(default = "default_lookahead_depth")]
+    pub lookahead_depth : usize,
+    //Routes kept at each string ahead when looking further than the next one
+    #[serde(default = "default_beam_width")]
+    pub beam_width : usize,
+    #[serde(default)]
+    pub layering : Layering,
+    //Indices into the colors, from the one wound first to the one on top. The colors' own order if not set.
+    #[serde(default)]
+    pub layer_order : Option<Vec<usize>>,
+    //Changes tried on the finished path to bring it closer to the input image, 0 keeps the greedy path
+    #[serde(default)]
+    pub refine_iterations : usize,
+    //Pixels across each square the refinement compares with the input as a whole
+    #[serde(default = "default_refine_downscale")]
+    pub refine_downscale : u32,
+    //How likely the refinement keeps changes which make the path worse at first, 0 only keeps improvements
+    #[serde(default)]
+    pub refine_temperature : f32
 }
 
-impl StringSettings
+fn default_pin_radius() -> f32 {0.95}
+fn default_edge_weight() -> f32 {0.4}
+fn default_bg_color() -> RgbColor {RgbColor([1., 1., 1.])}
+fn default_polygon_sides() -> usize {6}
+fn default_line_table_budget_mb() -> usize {512}
+fn default_nail_diameter_mm() -> f32 {1.5}
+fn default_thread_thickness_mm() -> f32 {0.25}
+fn default_thread_opacity() -> f32 {0.8}
+fn default_plateau_min_improvement() -> f32 {0.05}
+fn default_min_pin_gap() -> usize {1}
+fn default_lookahead_depth() -> usize {1}
+fn default_beam_width() -> usize {4}
+fn default_refine_downscale() -> u32 {4}
+fn random_seed() -> u64 {rand::random::<u32>() as u64}
+
+#[derive(Debug)]
+pub enum SettingsError
 {
-    pub fn get<T: StringSettingType>(&self,key: &str) -> Result<&T, String>
+    //The source could not be read or parsed at all
+    Read {source : String, message : String},
+    //A required key is missing
+    Missing {source : String, key : String},
+    //A key has a value of the wrong type
+    Type {source : String, key : String, expected : String, found : String},
+    //A key has a value of the right type, outside of its allowed range
+    Invalid {source : String, key : String, message : String}
+}
+
+impl fmt::Display for SettingsError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
     {
-        return T::get_setting(self, key);
+        match self
+        {
+            SettingsError::Read {source, message} => write!(f, "{source}: {message}"),
+            SettingsError::Missing {source, key} => write!(f, "{source}: missing required key `{key}`"),
+            SettingsError::Type {source, key, expected, found} => write!(f, "{source}: key `{key}` should be {expected}, found {found}"),
+            SettingsError::Invalid {source, key, message} => write!(f, "{source}: key `{key}` {message}")
+        }
     }
-
 }
 
-impl Default for StringSettings
+impl SettingsError
 {
-    fn default() -> Self {
-        let usize_keys = ["pin_count", "line_count","width","height"];
-        let string_keys = ["in_image_path", "out_image_path"];
-        let float_keys = ["pin_radius","edge_weight"];
-        let lab_keys = ["bg_color"];
-        let lab_vec_keys = ["str_colors"];
-
-        return StringSettings {
-            cfg: Config::default(),
-            size_vals:  itertools::zip(usize_keys, repeat(usize::default())).collect(),
-            string_vals:  itertools::zip(string_keys, repeat(String::default())).collect(),
-            float_vals: itertools::zip(float_keys, repeat(f32::default())).collect(),
-            lab_vals:  itertools::zip(lab_keys, repeat(Lab::default())).collect(),
-            lab_vec_vals: itertools::zip(lab_vec_keys, repeat(Vec::<Lab>::default())).collect()
-        };
+    fn from_config(source: &str, error: ConfigError) -> SettingsError
+    {
+        let source = source.to_string();
+        match error
+        {
+            ConfigError::Type {origin, unexpected, expected, key} => SettingsError::Type
+            {
+                source: origin.unwrap_or(source),
+                key: key.unwrap_or_default(),
+                expected: expected.to_string(),
+                found: unexpected.to_string()
+            },
+            ConfigError::NotFound(key) => SettingsError::Missing {source, key},
+            ConfigError::Message(message) => match missing_field(&message)
+            {
+                Some(key) => SettingsError::Missing {source, key},
+                None => SettingsError::Read {source, message}
+            },
+            other => SettingsError::Read {source, message: other.to_string()}
+        }
     }
 }
 
-/*Attempts to read each default setting key from the given file
+//Serde reports missing fields as "missing field `key`"
+fn missing_field(message: &str) -> Option<String>
+{
+    message.strip_prefix("missing field `").and_then(|m| m.strip_suffix('`')).map(str::to_string)
+}
+
+/*Reads the settings from the given file, with environment variable overrides.
 
 Returns:
     Ok => A StringSettings containing the read in data
-    Err => An error regarding either a file or key error.
+    Err => An error naming the file, and the key if a key is missing or invalid.
  */
+pub fn read_string_settings<P: AsRef<Path>>(path : P) -> Result<StringSettings, SettingsError>
+{
+    read_string_settings_with_overrides(path, &[])
+}
 
-pub fn read_string_settings(path : &'static str) -> Result<StringSettings, ConfigError>
+/*Same as read_string_settings, but each (key, value) pair replaces the value read from the file.
+    Values are given as strings and converted to the type of their key, so only scalar keys can be overridden.
+ */
+pub fn read_string_settings_with_overrides<P: AsRef<Path>>(path : P, overrides : &[(String, String)]) -> Result<StringSettings, SettingsError>
 {
-    let mut ss: StringSettings = StringSettings::default();
-    let cfg = Config::builder()
-        .add_source(config::File::with_name(path))
-        .build()?;
+    let source = path.as_ref().display().to_string();
+    let builder = Config::builder()
+        .add_source(config::File::with_name(&source));
+    StringSettings::build(&source, builder, overrides)
+}
 
-    ss.cfg = cfg;
+impl StringSettings
+{
+    //Read settings from a string in the given format, with environment variable overrides
+    pub fn parse(text: &str, format: FileFormat) -> Result<StringSettings, SettingsError>
+    {
+        let builder = Config::builder()
+            .add_source(config::File::from_str(text, format));
+        StringSettings::build("<string>", builder, &[])
+    }
 
-    for (key, val) in ss.size_vals.iter_mut()
+    //Layer the environment and explicit overrides over the given sources, then deserialize and validate
+    fn build(source: &str, builder: config::ConfigBuilder<config::builder::DefaultState>, overrides: &[(String, String)]) -> Result<StringSettings, SettingsError>
     {
-        *val = ss.cfg.get_int(key)? as usize;
+        let mut builder = builder.add_source(Environment::with_prefix(ENV_PREFIX).prefix_separator("_"));
+        for (key, value) in overrides
+        {
+            builder = builder.set_override(key.as_str(), value.as_str())
+                .map_err(|e| SettingsError::from_config(source, e))?;
+        }
+        let cfg = builder.build().map_err(|e| SettingsError::from_config(source, e))?;
+        let keys: Vec<String> = cfg.clone().try_deserialize::<config::Map<String, config::Value>>()
+            .map(|map| map.into_keys().collect())
+            .unwrap_or_default();
+        let settings: StringSettings = cfg.try_deserialize().map_err(|e| SettingsError::from_config(source, e))?;
+        settings.validate(source)?;
+        for key in settings.unknown_keys(&keys)
+        {
+            log::warn!("{source}: unknown setting `{key}` is ignored.");
+        }
+        Ok(settings)
     }
-    for (key, val) in ss.string_vals.iter_mut()
+
+    //Keys which are not part of the settings
+    fn unknown_keys<'a>(&self, keys: &'a [String]) -> Vec<&'a String>
     {
-        *val = ss.cfg.get_string(key)?;
+        let known = match serde_json::to_value(self)
+        {
+            Ok(serde_json::Value::Object(map)) => map,
+            _ => return Vec::new()
+        };
+        keys.iter().filter(|k| !known.contains_key(k.as_str())).collect()
     }
-    for(key, val) in ss.float_vals.iter_mut()
-    { 
-        *val =  ss.cfg.get_float(key)? as f32;
+
+    //Check that every value is within its allowed range
+    pub fn validate(&self, source: &str) -> Result<(), SettingsError>
+    {
+        let invalid = |key: &str, message: String| Err(SettingsError::Invalid {source: source.to_string(), key: key.to_string(), message});
+        if !(self.pin_radius > 0. && self.pin_radius < 1.)
+        {
+            return invalid("pin_radius", format!("must be in (0,1), got {}", self.pin_radius));
+        }
+        if self.line_count == 0
+        {
+            return invalid("line_count", "must be greater than 0".to_string());
+        }
+        if self.pin_layout != PinLayoutKind::Custom && self.pin_spacing.is_none() && self.pin_count < 2
+        {
+            return invalid("pin_count", format!("must be at least 2, got {}", self.pin_count));
+        }
+        if self.str_colors.is_empty() && self.palette_size.is_none()
+        {
+            return invalid("str_colors", "must contain at least one color, or palette_size must be set".to_string());
+        }
+        if self.palette_size == Some(0)
+        {
+            return invalid("palette_size", "must be greater than 0".to_string());
+        }
+        if !(0. ..=1.).contains(&self.edge_weight)
+        {
+            return invalid("edge_weight", format!("must be in [0,1], got {}", self.edge_weight));
+        }
+        for (key, color) in self.str_colors.iter().map(|c| ("str_colors", c)).chain([("bg_color", &self.bg_color)])
+        {
+            if color.0.iter().any(|c| !(0. ..=1.).contains(c))
+            {
+                return invalid(key, format!("must have components in [0,1], got {:?}", color.0));
+            }
+        }
+        if let Some(spacing) = self.pin_spacing.filter(|s| *s <= 0.)
+        {
+            return invalid("pin_spacing", format!("must be greater than 0, got {spacing}"));
+        }
+        if self.polygon_sides < 3
+        {
+            return invalid("polygon_sides", format!("must be at least 3, got {}", self.polygon_sides));
+        }
+        if self.checkpoint_interval == Some(0)
+        {
+            return invalid("checkpoint_interval", "must be greater than 0".to_string());
+        }
+        if let Some(diameter) = self.board_diameter_mm.filter(|d| *d <= 0.)
+        {
+            return invalid("board_diameter_mm", format!("must be greater than 0, got {diameter}"));
+        }
+        if self.nail_diameter_mm < 0.
+        {
+            return invalid("nail_diameter_mm", format!("must not be negative, got {}", self.nail_diameter_mm));
+        }
+        if self.thread_thickness_mm <= 0.
+        {
+            return invalid("thread_thickness_mm", format!("must be greater than 0, got {}", self.thread_thickness_mm));
+        }
+        if let Some(distance) = self.viewing_distance_mm
+        {
+            if distance <= 0.
+            {
+                return invalid("viewing_distance_mm", format!("must be greater than 0, got {distance}"));
+            }
+            if self.board_diameter_mm.is_none()
+            {
+                return invalid("viewing_distance_mm", "needs board_diameter_mm, to know the size of a pixel".to_string());
+            }
+        }
+        if self.evaluation_interval == Some(0)
+        {
+            return invalid("evaluation_interval", "must be greater than 0".to_string());
+        }
+        if let Some(window) = self.plateau_window
+        {
+            match self.evaluation_interval
+            {
+                None => return invalid("plateau_window", "needs evaluation_interval, to know the quality over time".to_string()),
+                Some(interval) if window < interval => return invalid("plateau_window", format!("must be at least evaluation_interval ({interval}), got {window}")),
+                _ => ()
+            }
+        }
+        if !(0. ..180.).contains(&self.min_pin_angle_deg)
+        {
+            return invalid("min_pin_angle_deg", format!("must be in [0,180), got {}", self.min_pin_angle_deg));
+        }
+        if self.max_chord_uses == Some(0)
+        {
+            return invalid("max_chord_uses", "must be greater than 0".to_string());
+        }
+        if self.max_pin_wraps == Some(0)
+        {
+            return invalid("max_pin_wraps", "must be greater than 0".to_string());
+        }
+        if self.plateau_min_improvement < 0.
+        {
+            return invalid("plateau_min_improvement", format!("must not be negative, got {}", self.plateau_min_improvement));
+        }
+        if let Some(budget) = self.thread_budget_m
+        {
+            if budget <= 0.
+            {
+                return invalid("thread_budget_m", format!("must be greater than 0, got {budget}"));
+            }
+            if self.board_diameter_mm.is_none()
+            {
+                return invalid("thread_budget_m", "needs board_diameter_mm, to know the length of a string".to_string());
+            }
+        }
+        if let Some(order) = &self.layer_order
+        {
+            if self.layering == Layering::Interleaved
+            {
+                return invalid("layer_order", "needs layering = \"ordered\" or \"optimized\"".to_string());
+            }
+            let color_count = self.palette_size.unwrap_or(self.str_colors.len());
+            let mut sorted = order.clone();
+            sorted.sort();
+            if sorted != (0..color_count).collect::<Vec<usize>>()
+            {
+                return invalid("layer_order", format!("must list every color index from 0 to {} once, got {order:?}", color_count - 1));
+            }
+        }
+        if self.residual_penalty < 0.
+        {
+            return invalid("residual_penalty", format!("must not be negative, got {}", self.residual_penalty));
+        }
+        if self.lookahead_depth == 0
+        {
+            return invalid("lookahead_depth", "must be greater than 0".to_string());
+        }
+        if self.beam_width == 0
+        {
+            return invalid("beam_width", "must be greater than 0".to_string());
+        }
+        if self.refine_downscale == 0
+        {
+            return invalid("refine_downscale", "must be greater than 0".to_string());
+        }
+        if self.refine_temperature < 0.
+        {
+            return invalid("refine_temperature", format!("must not be negative, got {}", self.refine_temperature));
+        }
+        if !(self.thread_opacity > 0. && self.thread_opacity <= 1.)
+        {
+            return invalid("thread_opacity", format!("must be in (0,1], got {}", self.thread_opacity));
+        }
+        if self.width == Some(0) || self.height == Some(0)
+        {
+            return invalid(if self.width == Some(0) {"width"} else {"height"}, "must be greater than 0".to_string());
+        }
+        if self.pin_layout == PinLayoutKind::Custom && self.pin_file.is_none()
+        {
+            return invalid("pin_file", "is required by the custom pin_layout".to_string());
+        }
+        Ok(())
     }
-    for(key, val) in ss.lab_vals.iter_mut()
+
+    pub fn colors(&self) -> Vec<Lab>
     {
-        *val = parse_lab_color(&ss.cfg.get::<config::Value>(key)?)?;
+        self.str_colors.iter().map(|c| c.lab()).collect()
     }
-    for(key, val) in ss.lab_vec_vals.iter_mut()
+
+    pub fn background(&self) -> Lab
     {
-        *val = parse_lab_colors(&ss.cfg.get_array(key)?)?;
+        self.bg_color.lab()
     }
-    Ok(ss)
-}
-fn parse_lab_colors(val_vec: &Vec<config::Value>) -> Result<Vec<Lab>, ConfigError>
-{
-    let mut col_vec: Vec<Lab> = vec![Lab::new(0.,0.,0.);val_vec.len()];
-    for i in 0..val_vec.len()
+
+    //The given seed, or a random one which is kept from then on
+    pub fn resolve_seed(&mut self) -> u64
     {
-        col_vec[i] = parse_lab_color(&val_vec[i])?;
+        *self.seed.get_or_insert_with(random_seed)
     }
-    Ok(col_vec)
-}
 
-fn parse_lab_color(s_color_rgb: &config::Value) -> Result<Lab, ConfigError>
-{
-    Ok(parse_rgb_color(s_color_rgb)?.into_color())
+    //Where checkpoints are written, by default next to the output images. The name does not depend on the seed,
+    //  so that a run with a random seed can be resumed from the same settings
+    pub fn checkpoint_file(&self) -> String
+    {
+        match &self.checkpoint_path
+        {
+            Some(path) => path.clone(),
+            None =>
+            {
+                let prefix = Path::new(&self.in_image_path).file_prefix().and_then(|p| p.to_str()).unwrap_or("path");
+                format!("{}{prefix}.checkpoint", self.out_image_path)
+            }
+        }
+    }
+
+    //Stable hash of every setting which affects the generated path
+    pub fn path_hash(&self) -> u64
+    {
+        let relevant = StringSettings {checkpoint_interval: None, checkpoint_path: None, ..self.clone()};
+        let json = serde_json::to_string(&relevant).unwrap_or_default();
+        //FNV-1a, so that the hash does not change between builds
+        json.bytes().fold(0xcbf29ce484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+    }
 }
 
-fn parse_rgb_color(s_color: &config::Value) -> Result<Srgb, ConfigError>
+#[cfg(test)]
+mod tests
 {
-    let c_arr = s_color.clone().into_array()?;
-    Ok(Srgb::new(
-        c_arr[0].clone().into_float()? as f32,
-        c_arr[1].clone().into_float()? as f32,
-        c_arr[2].clone().into_float()? as f32
-    ))
-}
+    use super::*;
+
+    const MINIMAL : &str = r#"
+in_image_path = "in.png"
+out_image_path = "out/"
+pin_count = 200
+line_count = 100
+str_colors = [[0,0,0]]
+"#;
+
+    #[test]
+    fn optional_keys_have_defaults()
+    {
+        let settings = StringSettings::parse(MINIMAL, FileFormat::Toml).unwrap();
+        assert_eq!(settings.pin_radius, 0.95);
+        assert_eq!(settings.bg_color, RgbColor([1., 1., 1.]));
+        assert_eq!(settings.pin_layout, PinLayoutKind::Circle);
+        let json = serde_json::to_string(&settings).unwrap();
+        assert_eq!(StringSettings::parse(&json, FileFormat::Json).unwrap().seed, settings.seed);
+    }
 
+    #[test]
+    fn errors_name_the_key()
+    {
+        let wrong_type = StringSettings::parse(&MINIMAL.replace("line_count = 100", "line_count = \"many\""), FileFormat::Toml).unwrap_err();
+        assert!(matches!(&wrong_type, SettingsError::Type {key, ..} if key == "line_count"), "{wrong_type}");
+        let missing = StringSettings::parse(&MINIMAL.replace("pin_count = 200", ""), FileFormat::Toml).unwrap_err();
+        assert!(matches!(&missing, SettingsError::Missing {key, ..} if key == "pin_count"), "{missing}");
+        let out_of_range = StringSettings::parse(&format!("{MINIMAL}pin_radius = 1.5"), FileFormat::Toml).unwrap_err();
+        assert!(matches!(&out_of_range, SettingsError::Invalid {key, ..} if key == "pin_radius"), "{out_of_range}");
+        let wrong_color = StringSettings::parse(&MINIMAL.replace("[[0,0,0]]", "[]"), FileFormat::Toml).unwrap_err();
+        assert!(matches!(&wrong_color, SettingsError::Invalid {key, ..} if key == "str_colors"), "{wrong_color}");
+    }
+}
@@ -0,0 +1,119 @@
+use crate::image_module::quantize::closest_in_catalog;
+
+use palette::{Lab, Srgb, IntoColor};
+use serde::{Serialize, Deserialize};
+
+//A purchasable thread
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Thread
+{
+    pub manufacturer : String,
+    pub code : String,
+    pub name : String,
+    pub lab : [f32; 3],
+    #[serde(default)]
+    pub price_per_m : Option<f32>
+}
+
+impl Thread
+{
+    pub fn color(&self) -> Lab
+    {
+        Lab::new(self.lab[0], self.lab[1], self.lab[2])
+    }
+}
+
+/*Threads read from a CSV file with manufacturer, code and name columns, the color either as an srgb column of hex
+    values like #1a2b3c or as l, a and b columns, and an optional price_per_m column.
+ */
+#[derive(Debug)]
+pub struct ThreadCatalog
+{
+    pub threads : Vec<Thread>
+}
+
+impl ThreadCatalog
+{
+    pub fn load(path: &str) -> Result<ThreadCatalog, String>
+    {
+        let mut reader = csv::Reader::from_path(path).map_err(|e| format!("{path}: {e}"))?;
+        let headers = reader.headers().map_err(|e| format!("{path}: {e}"))?.clone();
+        let column = |name: &str| headers.iter().position(|h| h.trim().eq_ignore_ascii_case(name));
+        let required = |name: &str| column(name).ok_or(format!("{path}: missing column \"{name}\""));
+        let (manufacturer_col, code_col, name_col) = (required("manufacturer")?, required("code")?, required("name")?);
+        let srgb_col = column("srgb");
+        let lab_cols = match srgb_col
+        {
+            Some(_) => None,
+            None => Some((required("l")?, required("a")?, required("b")?))
+        };
+        let price_col = column("price_per_m");
+
+        let mut threads = Vec::new();
+        for (row, record) in reader.records().enumerate()
+        {
+            let record = record.map_err(|e| format!("{path}: {e}"))?;
+            let row_error = |what: &str| format!("{path}: row {} {what}", row + 1);
+            let text = |col: usize| record.get(col).unwrap_or("").trim().to_string();
+            let number = |col: usize| record.get(col).and_then(|v| v.trim().parse::<f32>().ok());
+            let lab = match (srgb_col, lab_cols)
+            {
+                (Some(col), _) => parse_hex(&text(col)).ok_or(row_error("does not have an srgb color like #1a2b3c"))?,
+                (None, Some((l, a, b))) => match (number(l), number(a), number(b))
+                {
+                    (Some(l), Some(a), Some(b)) => [l, a, b],
+                    _ => return Err(row_error("does not have numeric l, a and b values"))
+                },
+                (None, None) => unreachable!()
+            };
+            let price_per_m = match price_col.map(text).filter(|p| !p.is_empty())
+            {
+                Some(price) => Some(price.parse().map_err(|_| row_error("does not have a numeric price_per_m"))?),
+                None => None
+            };
+            threads.push(Thread {manufacturer: text(manufacturer_col), code: text(code_col), name: text(name_col), lab, price_per_m});
+        }
+        if threads.is_empty()
+        {
+            return Err(format!("{path}: the catalog is empty"));
+        }
+        Ok(ThreadCatalog {threads})
+    }
+
+    //The thread to use for each color, using each thread at most once while there are enough
+    pub fn closest_threads(&self, colors: &[Lab]) -> Vec<Thread>
+    {
+        let catalog: Vec<Lab> = self.threads.iter().map(Thread::color).collect();
+        closest_in_catalog(colors, &catalog).into_iter().map(|idx| self.threads[idx].clone()).collect()
+    }
+}
+
+fn parse_hex(hex: &str) -> Option<[f32; 3]>
+{
+    let hex = hex.strip_prefix('#').unwrap_or(hex);
+    if hex.len() != 6 {return None};
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok().map(|c| c as f32 / 255.);
+    let lab: Lab = Srgb::new(channel(0)?, channel(2)?, channel(4)?).into_color();
+    Some([lab.l, lab.a, lab.b])
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn colors_resolve_to_threads()
+    {
+        let path = std::env::temp_dir().join("stringwind_threads.csv");
+        std::fs::write(&path, "manufacturer,code,name,srgb,price_per_m\nAcme,100,Black,#000000,0.05\nAcme,200,Red,#ff0000,\n").unwrap();
+        let catalog = ThreadCatalog::load(path.to_str().unwrap()).unwrap();
+        assert_eq!(catalog.threads[0].price_per_m, Some(0.05));
+        assert_eq!(catalog.threads[1].price_per_m, None);
+        let threads = catalog.closest_threads(&[Lab::new(50., 70., 50.), Lab::new(10., 0., 0.)]);
+        assert_eq!(threads.iter().map(|t| t.code.as_str()).collect::<Vec<_>>(), vec!["200", "100"]);
+
+        std::fs::write(&path, "manufacturer,code,name\nAcme,100,Black\n").unwrap();
+        assert!(ThreadCatalog::load(path.to_str().unwrap()).unwrap_err().contains("\"l\""));
+    }
+}
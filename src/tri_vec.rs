@@ -1,6 +1,7 @@
 use std::cmp::Ordering;
+use serde::{Serialize, Deserialize};
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct TriVec<T>
 {
     pub size : usize,
@@ -33,6 +34,16 @@ impl<T: Default + Clone + 'static> TriVec<T>
         assert!(index.0 < self.size);
         &mut self.data[index.0][index.1]
     }
+    pub fn get(&self, x: usize, y: usize) -> &T
+    {
+        let index = match x.cmp(&y)
+        {
+            Ordering::Greater => (x,y),
+            _ => (y,x)
+        };
+        assert!(index.0 < self.size);
+        &self.data[index.0][index.1]
+    }
     #[allow(dead_code)]
     pub fn set(&mut self, x: usize, y:usize, value: T)
     {
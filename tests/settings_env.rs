@@ -0,0 +1,25 @@
+/*Environment variables are shared by the whole process, so the overrides are tested in their own test binary
+    rather than next to the other settings tests, which would read them while they are set.
+ */
+use stringwind::string_path::string_setting::{read_string_settings, read_string_settings_with_overrides};
+
+#[test]
+fn environment_overrides_file_and_set_overrides_environment()
+{
+    let path = std::env::temp_dir().join("stringwind_env_settings.toml");
+    std::fs::write(&path, "in_image_path = \"in.png\"\nout_image_path = \"out/\"\npin_count = 200\nline_count = 100\nedge_weight = 0.1\nstr_colors = [[0,0,0]]\n").unwrap();
+    std::env::set_var("STRINGWIND_PIN_COUNT", "300");
+    std::env::set_var("STRINGWIND_EDGE_WEIGHT", "0.3");
+
+    let settings = read_string_settings(&path).unwrap();
+    assert_eq!((settings.pin_count, settings.edge_weight, settings.line_count), (300, 0.3, 100));
+
+    //Overrides given on the command line with --set come last
+    let overrides = [("pin_count".to_string(), "400".to_string())];
+    let settings = read_string_settings_with_overrides(&path, &overrides).unwrap();
+    assert_eq!((settings.pin_count, settings.edge_weight), (400, 0.3));
+
+    std::env::remove_var("STRINGWIND_PIN_COUNT");
+    std::env::remove_var("STRINGWIND_EDGE_WEIGHT");
+    assert_eq!(read_string_settings(&path).unwrap().pin_count, 200);
+}